@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use chip_8::instruction::OpCodeInstruction;
+
+// Every 16-bit word should decode to an instruction or None, never panic.
+// Input is read as big-endian words, the same way the interpreter fetches them.
+fuzz_target!(|data: &[u8]| {
+    for word in data.chunks_exact(2) {
+        let opcode = (word[0] as u16) << 8 | word[1] as u16;
+        let _ = OpCodeInstruction::new(opcode).process_opcode();
+    }
+});
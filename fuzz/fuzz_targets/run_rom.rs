@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use chip_8::chip8::Chip8;
+
+const MAX_CYCLES: usize = 10_000;
+const CYCLES_PER_TIMER_TICK: usize = 8;
+
+// Arbitrary ROMs may stop with an error, but must never panic.
+fuzz_target!(|rom: &[u8]| {
+    let mut chip8 = Chip8::with_seed(rom, 0);
+
+    for cycle in 0..MAX_CYCLES {
+        if chip8.cycle().is_err() {
+            break;
+        }
+        if cycle % CYCLES_PER_TIMER_TICK == 0 {
+            chip8.tick_timers();
+        }
+    }
+});
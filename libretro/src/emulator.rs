@@ -0,0 +1,113 @@
+use chip_8::beeper::{TimedBeeper, Tone, SAMPLE_RATE};
+use chip_8::chip8::STATE_LEN;
+use chip_8::palette::Palette;
+use chip_8::{Chip8, Keypad, Screen, StateError, CHIP8_HEIGHT, CHIP8_WIDTH};
+
+use crate::api::RETRO_LOG_ERROR;
+use crate::log;
+use crate::options::Options;
+
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// A loaded game, with the picture and sound of the frame it last ran.
+pub struct Emulator {
+    rom: Vec<u8>,
+    chip8: Chip8,
+    options: Options,
+    beeper: TimedBeeper,
+    frame: [u32; CHIP8_WIDTH * CHIP8_HEIGHT], // XRGB8888
+    audio: [i16; SAMPLES_PER_FRAME * 2], // interleaved stereo
+    halted: bool, // the program hit an error, so it's left showing its last frame
+}
+
+impl Emulator {
+    pub fn new(rom: Vec<u8>, options: Options) -> Self {
+        let mut emulator = Emulator {
+            chip8: Chip8::new(&rom),
+            rom,
+            options,
+            beeper: TimedBeeper::new(SAMPLE_RATE, Tone::default()),
+            frame: [0; CHIP8_WIDTH * CHIP8_HEIGHT],
+            audio: [0; SAMPLES_PER_FRAME * 2],
+            halted: false,
+        };
+        emulator.set_options(options);
+        emulator
+    }
+
+    /// Starts the game again from the beginning.
+    pub fn reset(&mut self) {
+        *self = Emulator::new(std::mem::take(&mut self.rom), self.options);
+    }
+
+    /// Applies new options straight away, without restarting the game.
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+        self.chip8.set_platform(options.platform);
+        self.chip8.set_quirks(options.quirks());
+        self.draw();
+    }
+
+    /// Runs one 60th of a second with the keys held on `keypad`.
+    pub fn run_frame<K: Keypad>(&mut self, keypad: &mut K) {
+        if !self.halted {
+            let mut screen = Framebuffer { pixels: &mut self.frame, palette: self.options.palette };
+            match self.chip8.run_frame(self.options.speed, keypad, &mut screen) {
+                Ok(Some(value)) => self.beeper.set_timer(value),
+                Ok(None) => (),
+                Err(e) => {
+                    log(RETRO_LOG_ERROR, &format!("The game stopped: {}", e));
+                    self.halted = true;
+                }
+            }
+        }
+
+        let mut samples = [0.0; SAMPLES_PER_FRAME];
+        self.beeper.fill(&mut samples);
+        for (frame, sample) in self.audio.chunks_exact_mut(2).zip(samples.iter()) {
+            let sample = (sample * f32::from(i16::MAX)) as i16;
+            frame.copy_from_slice(&[sample, sample]);
+        }
+    }
+
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    pub fn audio(&self) -> &[i16] {
+        &self.audio
+    }
+
+    pub fn save_state_to(&self, out: &mut [u8; STATE_LEN]) {
+        self.chip8.save_state_to(out);
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        self.chip8.load_state(state)?;
+        self.beeper.set_timer(self.chip8.sound_timer());
+        self.halted = false;
+        self.draw();
+        Ok(())
+    }
+
+    // Redraws the frame from the display as it is, for when the palette or state changes while halted.
+    fn draw(&mut self) {
+        Framebuffer { pixels: &mut self.frame, palette: self.options.palette }.draw(self.chip8.display());
+    }
+}
+
+struct Framebuffer<'a> {
+    pixels: &'a mut [u32; CHIP8_WIDTH * CHIP8_HEIGHT],
+    palette: Palette,
+}
+
+impl Screen for Framebuffer<'_> {
+    fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+        for (row, pixels) in display.iter().zip(self.pixels.chunks_exact_mut(CHIP8_WIDTH)) {
+            for (&pixel, out) in row.iter().zip(pixels.iter_mut()) {
+                let color = self.palette.color(pixel);
+                *out = u32::from(color.0) << 16 | u32::from(color.1) << 8 | u32::from(color.2);
+            }
+        }
+    }
+}
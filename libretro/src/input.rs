@@ -0,0 +1,48 @@
+use std::os::raw::c_uint;
+
+use chip_8::chip8::NUM_KEYS;
+use chip_8::Keypad;
+
+use crate::api::*;
+
+/// The CHIP-8 key each joypad button presses. The d-pad is 2/4/6/8 and A is 5, which is how most
+/// games move and fire; the other keys go on the remaining buttons.
+pub const JOYPAD: [(c_uint, usize, &str); NUM_KEYS] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2, "2 (up)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8, "8 (down)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4, "4 (left)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6, "6 (right)\0"),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5, "5\0"),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0, "0\0"),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x3, "3\0"),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x1, "1\0"),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x7, "7\0"),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0x9, "9\0"),
+    (RETRO_DEVICE_ID_JOYPAD_L2, 0xA, "A\0"),
+    (RETRO_DEVICE_ID_JOYPAD_R2, 0xB, "B\0"),
+    (RETRO_DEVICE_ID_JOYPAD_L3, 0xC, "C\0"),
+    (RETRO_DEVICE_ID_JOYPAD_R3, 0xD, "D\0"),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0xE, "E\0"),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xF, "F\0"),
+];
+
+/// The keyboard keys for each CHIP-8 key, keeping the keypad's shape on the left hand side of the
+/// keyboard as the emulator's own `qwerty` keymap does. libretro key codes are ASCII for these.
+const KEYBOARD: [u8; NUM_KEYS] = *b"x123qweasdzc4rfv";
+
+/// The keypad read through the frontend's input callback, from a joypad or keyboard on port 0.
+pub struct RetroKeypad(pub retro_input_state_t);
+
+impl Keypad for RetroKeypad {
+    fn keys(&mut self) -> [bool; NUM_KEYS] {
+        let held = |device, id| unsafe { (self.0)(0, device, 0, id) != 0 };
+        let mut keys = [false; NUM_KEYS];
+        for &(button, key, _) in JOYPAD.iter() {
+            keys[key] |= held(RETRO_DEVICE_JOYPAD, button);
+        }
+        for (key, &code) in KEYBOARD.iter().enumerate() {
+            keys[key] |= held(RETRO_DEVICE_KEYBOARD, c_uint::from(code));
+        }
+        keys
+    }
+}
@@ -0,0 +1,330 @@
+//! The emulator as a libretro core, so it runs inside RetroArch and other libretro frontends.
+//!
+//! The frontend loads the ROM and calls `retro_run` 60 times a second. Each call runs a frame of
+//! the game with the keypad read from the frontend's joypad or keyboard, and hands back the picture
+//! as XRGB8888 and a 60th of a second of the beeper. Speed, platform, quirks and palette are core
+//! options, and save states are the interpreter's own.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::slice;
+use std::sync::{Mutex, MutexGuard};
+
+use chip_8::beeper::SAMPLE_RATE;
+use chip_8::chip8::{MAX_PROGRAM_SIZE, STATE_LEN};
+use chip_8::{CHIP8_HEIGHT, CHIP8_WIDTH};
+
+mod api;
+mod emulator;
+mod input;
+mod options;
+
+pub use crate::api::*;
+use crate::emulator::Emulator;
+use crate::input::{RetroKeypad, JOYPAD};
+use crate::options::{Options, VARIABLES};
+
+/// The callbacks the frontend has given the core.
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<retro_environment_t>,
+    video_refresh: Option<retro_video_refresh_t>,
+    audio_sample_batch: Option<retro_audio_sample_batch_t>,
+    input_poll: Option<retro_input_poll_t>,
+    input_state: Option<retro_input_state_t>,
+    log: Option<retro_log_printf_t>,
+}
+
+// libretro has no handle to pass around, so the callbacks and the running game are globals.
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    log: None,
+});
+static EMULATOR: Mutex<Option<Emulator>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn emulator() -> MutexGuard<'static, Option<Emulator>> {
+    EMULATOR.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => unsafe { environment(cmd, data) },
+        None => false,
+    }
+}
+
+/// Writes a message to the frontend's log, or to stderr if the frontend doesn't have one.
+pub(crate) fn log(level: c_uint, message: &str) {
+    match (callbacks().log, CString::new(format!("{}\n", message))) {
+        (Some(log), Ok(line)) => unsafe { log(level, b"%s\0".as_ptr() as *const c_char, line.as_ptr()) },
+        _ => eprintln!("{}", message),
+    }
+}
+
+// The core options as the frontend has them set.
+fn read_options() -> Options {
+    let mut options = Options::default();
+    for (key, _) in VARIABLES.iter() {
+        let mut variable = retro_variable { key: key.as_ptr() as *const c_char, value: ptr::null() };
+        if environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut _ as *mut c_void) && !variable.value.is_null() {
+            if let Ok(value) = unsafe { CStr::from_ptr(variable.value) }.to_str() {
+                options.set(key.trim_end_matches('\0'), value);
+            }
+        }
+    }
+    options
+}
+
+fn options_updated() -> bool {
+    let mut updated = false;
+    environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: retro_environment_t) {
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).environment = Some(callback);
+
+    let mut log = retro_log_callback { log: None };
+    if environment(RETRO_ENVIRONMENT_GET_LOG_INTERFACE, &mut log as *mut _ as *mut c_void) {
+        CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).log = log.log;
+    }
+
+    let mut variables: Vec<_> = VARIABLES
+        .iter()
+        .map(|(key, value)| retro_variable { key: key.as_ptr() as *const c_char, value: value.as_ptr() as *const c_char })
+        .collect();
+    variables.push(retro_variable { key: ptr::null(), value: ptr::null() });
+    environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: retro_video_refresh_t) {
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).video_refresh = Some(callback);
+}
+
+// Only the batch callback is used, but frontends set both
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: retro_audio_sample_t) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: retro_audio_sample_batch_t) {
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: retro_input_poll_t) {
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: retro_input_state_t) {
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner()).input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *emulator() = None;
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_info` to fill in.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut retro_system_info) {
+    *info = retro_system_info {
+        library_name: b"CHIP-8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` must point to a `retro_system_av_info` to fill in.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut retro_system_av_info) {
+    *info = retro_system_av_info {
+        geometry: retro_game_geometry {
+            base_width: CHIP8_WIDTH as c_uint,
+            base_height: CHIP8_HEIGHT as c_uint,
+            max_width: CHIP8_WIDTH as c_uint,
+            max_height: CHIP8_HEIGHT as c_uint,
+            aspect_ratio: CHIP8_WIDTH as f32 / CHIP8_HEIGHT as f32,
+        },
+        timing: retro_system_timing { fps: 60.0, sample_rate: f64::from(SAMPLE_RATE) },
+    };
+}
+
+// Every port reads the same keypad, whatever is plugged in
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(emulator) = emulator().as_mut() {
+        emulator.reset();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut emulator = emulator();
+    let (emulator, input_state) = match (emulator.as_mut(), callbacks.input_state) {
+        (Some(emulator), Some(input_state)) => (emulator, input_state),
+        _ => return,
+    };
+
+    if let Some(input_poll) = callbacks.input_poll {
+        unsafe { input_poll() };
+    }
+    if options_updated() {
+        emulator.set_options(read_options());
+    }
+    emulator.run_frame(&mut RetroKeypad(input_state));
+
+    if let Some(video_refresh) = callbacks.video_refresh {
+        let frame = emulator.frame();
+        let pitch = CHIP8_WIDTH * std::mem::size_of::<u32>();
+        unsafe { video_refresh(frame.as_ptr() as *const c_void, CHIP8_WIDTH as c_uint, CHIP8_HEIGHT as c_uint, pitch) };
+    }
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        // The frontend may take fewer frames than it's given, so keep going until it has them all
+        let mut audio = emulator.audio();
+        while !audio.is_empty() {
+            let taken = unsafe { audio_sample_batch(audio.as_ptr(), audio.len() / 2) };
+            if taken == 0 {
+                break;
+            }
+            audio = &audio[(taken * 2).min(audio.len())..];
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_LEN
+}
+
+/// # Safety
+///
+/// `data` must point to `size` bytes that can be written.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    match emulator().as_ref() {
+        Some(emulator) if size >= STATE_LEN => {
+            emulator.save_state_to(&mut *(data as *mut [u8; STATE_LEN]));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// # Safety
+///
+/// `data` must point to `size` bytes that can be read.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    match emulator().as_mut() {
+        Some(emulator) => {
+            let state = slice::from_raw_parts(data as *const u8, size);
+            match emulator.load_state(state) {
+                Ok(()) => true,
+                Err(e) => {
+                    log(RETRO_LOG_ERROR, &format!("Couldn't load state: {}", e));
+                    false
+                }
+            }
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+///
+/// `game` must be null or point to a `retro_game_info` whose `data` holds `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const retro_game_info) -> bool {
+    let game = match game.as_ref() {
+        Some(game) if !game.data.is_null() => game,
+        _ => return false,
+    };
+    if game.size > MAX_PROGRAM_SIZE {
+        log(RETRO_LOG_ERROR, &format!("The ROM is {} bytes, more than the {} that fit in memory", game.size, MAX_PROGRAM_SIZE));
+        return false;
+    }
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        log(RETRO_LOG_ERROR, "The frontend can't show XRGB8888");
+        return false;
+    }
+
+    let mut descriptors: Vec<_> = JOYPAD
+        .iter()
+        .map(|&(id, _, description)| retro_input_descriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id,
+            description: description.as_ptr() as *const c_char,
+        })
+        .collect();
+    descriptors.push(retro_input_descriptor { port: 0, device: 0, index: 0, id: 0, description: ptr::null() });
+    environment(RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr() as *mut c_void);
+
+    let rom = slice::from_raw_parts(game.data as *const u8, game.size).to_vec();
+    *emulator() = Some(Emulator::new(rom, read_options()));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const retro_game_info, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *emulator() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+// Memory isn't exposed, so there are no achievements or cheats that poke it
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
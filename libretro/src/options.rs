@@ -0,0 +1,95 @@
+use chip_8::palette::Palette;
+use chip_8::{Platform, Quirks};
+
+/// The core options, as `(key, "Description; default|other|values")`. Both end in a NUL so they
+/// can be handed to the frontend as they are.
+pub const VARIABLES: [(&str, &str); 8] = [
+    ("chip8_speed\0", "Instructions per frame; 8|10|12|15|20|30|50|100|200|1|2|3|4|5|6|7\0"),
+    ("chip8_platform\0", "Platform; chip8|vip\0"),
+    ("chip8_palette\0", "Palette; classic|green|amber|lcd|octo|high-contrast|colorblind\0"),
+    ("chip8_quirk_shift_uses_vy\0", "Quirk: shifts use VY; platform|on|off\0"),
+    ("chip8_quirk_load_store_increments_i\0", "Quirk: FX55/FX65 increment I; platform|on|off\0"),
+    ("chip8_quirk_jump_uses_vx\0", "Quirk: BNNN jumps with VX; platform|on|off\0"),
+    ("chip8_quirk_logic_resets_vf\0", "Quirk: AND/OR/XOR reset VF; platform|on|off\0"),
+    ("chip8_quirk_sprites_wrap\0", "Quirk: sprites wrap; platform|on|off\0"),
+];
+
+/// How the game runs, as chosen in the frontend's core options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    pub speed: u32,
+    pub platform: Platform,
+    pub palette: Palette,
+    quirks: [Option<bool>; 5], // in the order of `VARIABLES`, `None` leaving it to the platform
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { speed: 8, platform: Platform::default(), palette: Palette::default(), quirks: [None; 5] }
+    }
+}
+
+impl Options {
+    /// Sets the option `key` (without its NUL) to `value`. Values the core doesn't know are ignored.
+    pub fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "chip8_speed" => self.speed = value.parse().unwrap_or(self.speed),
+            "chip8_platform" => self.platform = value.parse().unwrap_or(self.platform),
+            "chip8_palette" => self.palette = Palette::preset(value).unwrap_or(self.palette),
+            _ => {
+                let quirk = VARIABLES[3..].iter().position(|(name, _)| name.trim_end_matches('\0') == key);
+                if let Some(index) = quirk {
+                    self.quirks[index] = match value {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        _ => None,
+                    };
+                }
+            }
+        }
+    }
+
+    /// The platform's quirks with the ones set in the options replacing them.
+    pub fn quirks(&self) -> Quirks {
+        let mut quirks = self.platform.quirks();
+        let mut fields = [
+            &mut quirks.shift_uses_vy,
+            &mut quirks.load_store_increments_i,
+            &mut quirks.jump_uses_vx,
+            &mut quirks.logic_resets_vf,
+            &mut quirks.sprites_wrap,
+        ];
+        for (field, set) in fields.iter_mut().zip(self.quirks.iter()) {
+            **field = set.unwrap_or(**field);
+        }
+        quirks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip_8::palette;
+
+    #[test]
+    fn every_palette_is_an_option() {
+        let (_, palettes) = VARIABLES[2];
+        let values: Vec<_> = palettes.trim_end_matches('\0').split("; ").nth(1).unwrap().split('|').collect();
+        let names: Vec<_> = palette::PRESETS.iter().map(|palette| palette.name).collect();
+        assert_eq!(values, names);
+    }
+
+    #[test]
+    fn quirk_options_override_the_platform() {
+        let mut options = Options::default();
+        options.set("chip8_platform", "vip");
+        assert_eq!(options.quirks(), Platform::CosmacVip.quirks());
+
+        options.set("chip8_quirk_sprites_wrap", "on");
+        options.set("chip8_quirk_shift_uses_vy", "off");
+        options.set("chip8_speed", "fast"); // not a number, so left alone
+        let quirks = options.quirks();
+        assert!(quirks.sprites_wrap && !quirks.shift_uses_vy && quirks.logic_resets_vf);
+        assert_eq!(options.speed, 8);
+    }
+}
@@ -0,0 +1,184 @@
+//! A minimal libretro frontend that drives the core through its C API, as RetroArch would.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_uint, c_void};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use chip_8_libretro::*;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+const SAMPLES_PER_FRAME: usize = 44100 / 60;
+
+/// What the core has told the frontend, and what the frontend will tell it.
+#[derive(Default)]
+struct Frontend {
+    pixel_format: Option<c_uint>,
+    options: HashMap<String, CString>, // the defaults from the core's option list, then any changes
+    options_updated: bool,
+    held: Vec<(c_uint, c_uint)>, // device and button
+    frame: Vec<u32>,
+    audio: Vec<i16>,
+}
+
+static FRONTEND: Mutex<Option<Frontend>> = Mutex::new(None);
+static CORE: Mutex<()> = Mutex::new(()); // the core is global, so tests take turns
+
+fn frontend() -> MutexGuard<'static, Option<Frontend>> {
+    FRONTEND.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    let mut frontend = frontend();
+    let frontend = frontend.as_mut().unwrap();
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+            frontend.pixel_format = Some(*(data as *const c_uint));
+            true
+        }
+        RETRO_ENVIRONMENT_SET_VARIABLES => {
+            let mut variable = data as *const retro_variable;
+            while !(*variable).key.is_null() {
+                let key = CStr::from_ptr((*variable).key).to_str().unwrap().to_string();
+                let value = CStr::from_ptr((*variable).value).to_str().unwrap();
+                let default = value.split("; ").nth(1).unwrap().split('|').next().unwrap();
+                frontend.options.insert(key, CString::new(default).unwrap());
+                variable = variable.add(1);
+            }
+            true
+        }
+        RETRO_ENVIRONMENT_GET_VARIABLE => {
+            let variable = &mut *(data as *mut retro_variable);
+            let key = CStr::from_ptr(variable.key).to_str().unwrap();
+            match frontend.options.get(key) {
+                Some(value) => {
+                    variable.value = value.as_ptr();
+                    true
+                }
+                None => false,
+            }
+        }
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = std::mem::take(&mut frontend.options_updated);
+            true
+        }
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS => true,
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    assert_eq!((width as usize, height as usize, pitch), (WIDTH, HEIGHT, WIDTH * 4));
+    let pixels = std::slice::from_raw_parts(data as *const u32, WIDTH * HEIGHT);
+    frontend().as_mut().unwrap().frame = pixels.to_vec();
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = std::slice::from_raw_parts(data, frames * 2);
+    frontend().as_mut().unwrap().audio.extend_from_slice(samples);
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let frontend = frontend();
+    let held = port == 0 && frontend.as_ref().unwrap().held.contains(&(device, id));
+    i16::from(held)
+}
+
+/// Starts the core with `rom` loaded, leaving it locked for the test.
+fn load(rom: &[u8]) -> MutexGuard<'static, ()> {
+    let core = CORE.lock().unwrap_or_else(|e| e.into_inner());
+    *frontend() = Some(Frontend::default());
+    assert_eq!(retro_api_version(), 1);
+    retro_set_environment(environment);
+    retro_set_video_refresh(video_refresh);
+    retro_set_audio_sample(audio_sample);
+    retro_set_audio_sample_batch(audio_sample_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+
+    let game = retro_game_info { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+    assert!(unsafe { retro_load_game(&game) });
+    core
+}
+
+fn unload() {
+    retro_unload_game();
+    retro_deinit();
+}
+
+fn run(frames: usize) {
+    for _ in 0..frames {
+        retro_run();
+    }
+}
+
+fn with_frontend<T>(f: impl FnOnce(&mut Frontend) -> T) -> T {
+    f(frontend().as_mut().unwrap())
+}
+
+#[test]
+fn shows_the_game_and_plays_its_sound() {
+    let _core = load(include_bytes!("../../ROMS/IBM Logo.ch8"));
+    let mut info = unsafe { std::mem::zeroed::<retro_system_av_info>() };
+    unsafe { retro_get_system_av_info(&mut info) };
+    assert_eq!((info.geometry.base_width, info.geometry.base_height), (64, 32));
+    assert_eq!((info.timing.fps, info.timing.sample_rate), (60.0, 44100.0));
+
+    run(10);
+    with_frontend(|frontend| {
+        assert_eq!(frontend.pixel_format, Some(RETRO_PIXEL_FORMAT_XRGB8888));
+        assert!(frontend.frame.contains(&0xFF_FF_FF) && frontend.frame.contains(&0));
+        assert_eq!(frontend.audio.len(), 10 * SAMPLES_PER_FRAME * 2);
+        assert!(frontend.audio.iter().all(|&sample| sample == 0));
+    });
+
+    // The palette is a core option, and changing it shows straight away
+    with_frontend(|frontend| {
+        frontend.options.insert("chip8_palette".to_string(), CString::new("amber").unwrap());
+        frontend.options_updated = true;
+    });
+    run(1);
+    with_frontend(|frontend| assert!(frontend.frame.contains(&0xFF_B0_00)));
+    unload();
+}
+
+#[test]
+fn keypad_comes_from_the_joypad() {
+    // LD V0, K then LD ST, V0, looping on the jump after
+    let _core = load(&[0xF0, 0x0A, 0xF0, 0x18, 0x12, 0x04]);
+    run(5);
+    with_frontend(|frontend| {
+        assert!(frontend.audio.iter().all(|&sample| sample == 0));
+        frontend.held.push((RETRO_DEVICE_JOYPAD, RETRO_DEVICE_ID_JOYPAD_A)); // key 5
+        frontend.audio.clear();
+    });
+    run(5);
+    with_frontend(|frontend| assert!(frontend.audio.iter().any(|&sample| sample != 0)));
+    unload();
+}
+
+#[test]
+fn save_states_restore_the_game() {
+    let _core = load(include_bytes!("../../ROMS/IBM Logo.ch8"));
+    run(1); // part way through drawing the logo
+    let mut state = vec![0; retro_serialize_size()];
+    assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+    run(1);
+    let next = with_frontend(|frontend| frontend.frame.clone());
+
+    run(30);
+    assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+    run(1);
+    with_frontend(|frontend| assert_eq!(frontend.frame, next));
+
+    assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 10) });
+    unload();
+}
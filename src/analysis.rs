@@ -0,0 +1,60 @@
+use chip_8::chip8::PROGRAM_START;
+use chip_8::instruction::{Instruction, OpCodeInstruction};
+
+/// A ROM as it sits in memory from 0x200, for looking at without running it.
+pub struct Program<'a> {
+    rom: &'a [u8],
+}
+
+impl<'a> Program<'a> {
+    pub fn new(rom: &'a [u8]) -> Self {
+        Program { rom }
+    }
+
+    /// The address just past the last byte of the ROM.
+    pub fn end(&self) -> usize {
+        PROGRAM_START + self.rom.len()
+    }
+
+    /// Whether a whole instruction at `addr` is part of the ROM.
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= PROGRAM_START && addr + 2 <= self.end()
+    }
+
+    pub fn opcode(&self, addr: usize) -> Option<u16> {
+        if self.contains(addr) {
+            let offset = addr - PROGRAM_START;
+            Some(u16::from_be_bytes([self.rom[offset], self.rom[offset + 1]]))
+        } else {
+            None
+        }
+    }
+
+    /// The instruction at `addr`, or `None` if it doesn't decode or isn't in the ROM.
+    pub fn decode(&self, addr: usize) -> Option<Instruction> {
+        self.opcode(addr).and_then(|opcode| OpCodeInstruction::new(opcode).process_opcode())
+    }
+}
+
+/// Where an instruction sends the program counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next, // on to the next instruction
+    Skip, // the next instruction, or the one after if the condition holds
+    Jump(usize),
+    Call(usize), // the subroutine, then the next instruction once it returns
+    Return,
+    Indirect(usize), // JP V0 goes somewhere from the address on, depending on a register
+}
+
+pub fn flow(instruction: Instruction) -> Flow {
+    match instruction {
+        Instruction::JMP(addr) => Flow::Jump(usize::from(addr)),
+        Instruction::CALL(addr) => Flow::Call(usize::from(addr)),
+        Instruction::RET() => Flow::Return,
+        Instruction::JPV0(addr) => Flow::Indirect(usize::from(addr)),
+        Instruction::SEQB(..) | Instruction::SNEQB(..) | Instruction::SRER(..) | Instruction::SNE(..)
+            | Instruction::SKP(_) | Instruction::SKNP(_) => Flow::Skip,
+        _ => Flow::Next,
+    }
+}
@@ -0,0 +1,263 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+const MAX_AMPLITUDE: f32 = 0.1; // full volume, leaving plenty of headroom
+const ENVELOPE_TIME: f32 = 0.005; // seconds to fade in or out, so starting and stopping doesn't click
+
+/// The shape of the beeper's wave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform '{}', expected square, sine, triangle or noise", s)),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Noise => "noise",
+        })
+    }
+}
+
+/// What happens to the beeper while fast-forwarding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FastForwardSound {
+    #[default]
+    Mute,
+    Pitch, // play the beeps faster and higher, like a tape
+}
+
+impl FromStr for FastForwardSound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mute" => Ok(FastForwardSound::Mute),
+            "pitch" => Ok(FastForwardSound::Pitch),
+            _ => Err(format!("unknown fast-forward sound '{}', expected mute or pitch", s)),
+        }
+    }
+}
+
+impl fmt::Display for FastForwardSound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FastForwardSound::Mute => "mute",
+            FastForwardSound::Pitch => "pitch",
+        })
+    }
+}
+
+/// What the beeper sounds like.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: f32, // in Hz. For noise, how often it changes value, twice a cycle
+    pub waveform: Waveform,
+    pub duty: f32, // fraction of each cycle a square wave spends high
+    pub volume: f32, // from 0 to 1
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone { frequency: 300.0, waveform: Waveform::Square, duty: 0.5, volume: 0.1 }
+    }
+}
+
+/// Generates the beeper's wave. Used both for live audio and for audio export,
+/// so recordings sound exactly like the emulator does.
+pub struct Beeper {
+    sample_rate: f32,
+    tone: Tone,
+    phase: f32,
+    level: f32, // envelope, fading towards the volume while on and towards 0 while off
+    noise: u32, // xorshift state for the noise waveform
+    noise_sample: f32,
+}
+
+impl Beeper {
+    pub fn new(sample_rate: u32, tone: Tone) -> Self {
+        Beeper {
+            sample_rate: sample_rate as f32,
+            tone,
+            phase: 0.0,
+            level: 0.0,
+            noise: 0x1234_5678,
+            noise_sample: 1.0,
+        }
+    }
+
+    /// Changes the tone. Volume changes fade in like the beeper starting does.
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.tone = tone;
+    }
+
+    /// Fills `out` with the tone, or with silence while the beeper is off, fading between the two.
+    pub fn fill(&mut self, out: &mut [f32], on: bool) {
+        let target = if on { self.tone.volume.clamp(0.0, 1.0) * MAX_AMPLITUDE } else { 0.0 };
+        let step = MAX_AMPLITUDE / (ENVELOPE_TIME * self.sample_rate);
+        let phase_inc = self.tone.frequency / self.sample_rate;
+
+        for x in out.iter_mut() {
+            self.level = if self.level < target {
+                (self.level + step).min(target)
+            } else {
+                (self.level - step).max(target)
+            };
+            if self.level == 0.0 {
+                // The wave stays where it was while silent, as if playback was paused
+                *x = 0.0;
+                continue;
+            }
+
+            *x = self.level * self.sample();
+            let next_phase = self.phase + phase_inc;
+            if self.tone.waveform == Waveform::Noise && (next_phase >= 1.0 || (self.phase < 0.5) != (next_phase < 0.5)) {
+                self.noise_sample = self.next_noise();
+            }
+            self.phase = next_phase % 1.0;
+        }
+    }
+
+    // The wave at the current phase, from -1 to 1.
+    fn sample(&self) -> f32 {
+        match self.tone.waveform {
+            Waveform::Square => if self.phase < self.tone.duty { 1.0 } else { -1.0 },
+            Waveform::Sine => (self.phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => self.noise_sample,
+        }
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// A beeper that counts the sound timer down itself, once every 1/60s of samples, so beeps last
+/// exactly as long as the program asked however the emulator's frames line up with the audio.
+pub struct TimedBeeper {
+    beeper: Beeper,
+    timer: u8,
+    sample_rate: u32,
+    samples_per_tick: usize,
+    samples_until_tick: usize,
+}
+
+impl TimedBeeper {
+    pub fn new(sample_rate: u32, tone: Tone) -> Self {
+        let samples_per_tick = (sample_rate / 60) as usize;
+        TimedBeeper {
+            beeper: Beeper::new(sample_rate, tone),
+            timer: 0,
+            sample_rate,
+            samples_per_tick,
+            samples_until_tick: samples_per_tick,
+        }
+    }
+
+    pub fn beeper(&mut self) -> &mut Beeper {
+        &mut self.beeper
+    }
+
+    /// Counts the timer down `rate` times faster than 60Hz, to keep up with the emulator
+    /// while it fast-forwards or runs in slow motion.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.samples_per_tick = ((self.sample_rate as f32 / (60.0 * rate)).round() as usize).max(1);
+        self.samples_until_tick = self.samples_until_tick.min(self.samples_per_tick);
+    }
+
+    /// Starts counting down from `value`, as the program just set the sound timer to it.
+    pub fn set_timer(&mut self, value: u8) {
+        self.timer = value;
+        self.samples_until_tick = self.samples_per_tick;
+    }
+
+    pub fn fill(&mut self, mut out: &mut [f32]) {
+        while !out.is_empty() {
+            let len = out.len().min(self.samples_until_tick);
+            let (now, rest) = out.split_at_mut(len);
+            self.beeper.fill(now, self.timer > 0);
+            out = rest;
+
+            self.samples_until_tick -= len;
+            if self.samples_until_tick == 0 {
+                self.timer = self.timer.saturating_sub(1);
+                self.samples_until_tick = self.samples_per_tick;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_and_stops_without_clicks() {
+        let tone = Tone { volume: 1.0, ..Tone::default() };
+        let mut beeper = Beeper::new(SAMPLE_RATE, tone);
+        let mut samples = vec![0.0; 2000];
+        beeper.fill(&mut samples[..1000], true);
+        beeper.fill(&mut samples[1000..], false);
+
+        let max_step = samples.windows(2).filter(|pair| pair[0].signum() == pair[1].signum())
+            .map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f32::max);
+        assert!(max_step < 0.001, "jumped by {}", max_step);
+        assert!(samples[500].abs() == MAX_AMPLITUDE);
+        assert_eq!(samples[1999], 0.0);
+    }
+
+    #[test]
+    fn shortest_beep_lasts_one_tick() {
+        let mut beeper = TimedBeeper::new(SAMPLE_RATE, Tone::default());
+        let mut samples = vec![0.0; 3000];
+        beeper.fill(&mut samples[..100]);
+        beeper.set_timer(1);
+        for chunk in samples[100..].chunks_mut(64) { // the audio device asks in small, uneven pieces
+            beeper.fill(chunk);
+        }
+
+        let tick = (SAMPLE_RATE / 60) as usize;
+        assert!(samples[..100].iter().all(|&sample| sample == 0.0));
+        assert!(samples[100..100 + tick].iter().filter(|&&sample| sample != 0.0).count() > tick * 9 / 10);
+        assert!(samples[100 + tick + 1000..].iter().all(|&sample| sample == 0.0)); // faded out
+    }
+
+    #[test]
+    fn fast_forward_shortens_beeps() {
+        let mut beeper = TimedBeeper::new(SAMPLE_RATE, Tone::default());
+        beeper.set_rate(4.0);
+        beeper.set_timer(4);
+        let mut samples = vec![0.0; 3000];
+        beeper.fill(&mut samples);
+
+        let tick = (SAMPLE_RATE / 60) as usize;
+        assert!(samples[tick - 100..tick].iter().any(|&sample| sample != 0.0));
+        assert!(samples[tick + 1000..].iter().all(|&sample| sample == 0.0)); // four ticks took one
+    }
+}
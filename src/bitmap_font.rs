@@ -0,0 +1,126 @@
+/// A 3 by 5 pixel font for printable ASCII, for text the emulator draws itself like the ROM
+/// browser. Lower case letters are drawn as capitals.
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+pub const ADVANCE: usize = GLYPH_WIDTH + 1; // a column of space between characters
+
+// Rows from the top, with the leftmost pixel in bit 2. Space to backquote, then the four after the
+// lower case letters.
+const GLYPHS: [[u8; GLYPH_HEIGHT]; 69] = [
+    [0b000, 0b000, 0b000, 0b000, 0b000], // space
+    [0b010, 0b010, 0b010, 0b000, 0b010], // !
+    [0b101, 0b101, 0b000, 0b000, 0b000], // "
+    [0b101, 0b111, 0b101, 0b111, 0b101], // #
+    [0b011, 0b110, 0b010, 0b011, 0b110], // $
+    [0b101, 0b001, 0b010, 0b100, 0b101], // %
+    [0b010, 0b101, 0b010, 0b101, 0b011], // &
+    [0b010, 0b010, 0b000, 0b000, 0b000], // '
+    [0b001, 0b010, 0b010, 0b010, 0b001], // (
+    [0b100, 0b010, 0b010, 0b010, 0b100], // )
+    [0b000, 0b101, 0b010, 0b101, 0b000], // *
+    [0b000, 0b010, 0b111, 0b010, 0b000], // +
+    [0b000, 0b000, 0b000, 0b010, 0b100], // ,
+    [0b000, 0b000, 0b111, 0b000, 0b000], // -
+    [0b000, 0b000, 0b000, 0b000, 0b010], // .
+    [0b001, 0b001, 0b010, 0b100, 0b100], // /
+    [0b111, 0b101, 0b101, 0b101, 0b111], // 0
+    [0b010, 0b110, 0b010, 0b010, 0b111], // 1
+    [0b110, 0b001, 0b010, 0b100, 0b111], // 2
+    [0b110, 0b001, 0b010, 0b001, 0b110], // 3
+    [0b101, 0b101, 0b111, 0b001, 0b001], // 4
+    [0b111, 0b100, 0b110, 0b001, 0b110], // 5
+    [0b011, 0b100, 0b110, 0b101, 0b010], // 6
+    [0b111, 0b001, 0b010, 0b010, 0b010], // 7
+    [0b010, 0b101, 0b010, 0b101, 0b010], // 8
+    [0b010, 0b101, 0b011, 0b001, 0b110], // 9
+    [0b000, 0b010, 0b000, 0b010, 0b000], // :
+    [0b000, 0b010, 0b000, 0b010, 0b100], // ;
+    [0b001, 0b010, 0b100, 0b010, 0b001], // <
+    [0b000, 0b111, 0b000, 0b111, 0b000], // =
+    [0b100, 0b010, 0b001, 0b010, 0b100], // >
+    [0b110, 0b001, 0b010, 0b000, 0b010], // ?
+    [0b010, 0b101, 0b111, 0b100, 0b011], // @
+    [0b010, 0b101, 0b111, 0b101, 0b101], // A
+    [0b110, 0b101, 0b110, 0b101, 0b110], // B
+    [0b011, 0b100, 0b100, 0b100, 0b011], // C
+    [0b110, 0b101, 0b101, 0b101, 0b110], // D
+    [0b111, 0b100, 0b110, 0b100, 0b111], // E
+    [0b111, 0b100, 0b110, 0b100, 0b100], // F
+    [0b011, 0b100, 0b101, 0b101, 0b011], // G
+    [0b101, 0b101, 0b111, 0b101, 0b101], // H
+    [0b111, 0b010, 0b010, 0b010, 0b111], // I
+    [0b001, 0b001, 0b001, 0b101, 0b010], // J
+    [0b101, 0b101, 0b110, 0b101, 0b101], // K
+    [0b100, 0b100, 0b100, 0b100, 0b111], // L
+    [0b101, 0b111, 0b111, 0b101, 0b101], // M
+    [0b110, 0b101, 0b101, 0b101, 0b101], // N
+    [0b010, 0b101, 0b101, 0b101, 0b010], // O
+    [0b110, 0b101, 0b110, 0b100, 0b100], // P
+    [0b010, 0b101, 0b101, 0b110, 0b011], // Q
+    [0b110, 0b101, 0b110, 0b101, 0b101], // R
+    [0b011, 0b100, 0b010, 0b001, 0b110], // S
+    [0b111, 0b010, 0b010, 0b010, 0b010], // T
+    [0b101, 0b101, 0b101, 0b101, 0b111], // U
+    [0b101, 0b101, 0b101, 0b101, 0b010], // V
+    [0b101, 0b101, 0b111, 0b111, 0b101], // W
+    [0b101, 0b101, 0b010, 0b101, 0b101], // X
+    [0b101, 0b101, 0b010, 0b010, 0b010], // Y
+    [0b111, 0b001, 0b010, 0b100, 0b111], // Z
+    [0b011, 0b010, 0b010, 0b010, 0b011], // [
+    [0b100, 0b100, 0b010, 0b001, 0b001], // \
+    [0b110, 0b010, 0b010, 0b010, 0b110], // ]
+    [0b010, 0b101, 0b000, 0b000, 0b000], // ^
+    [0b000, 0b000, 0b000, 0b000, 0b111], // _
+    [0b100, 0b010, 0b000, 0b000, 0b000], // `
+    [0b011, 0b010, 0b100, 0b010, 0b011], // {
+    [0b010, 0b010, 0b010, 0b010, 0b010], // |
+    [0b110, 0b010, 0b001, 0b010, 0b110], // }
+    [0b000, 0b011, 0b110, 0b000, 0b000], // ~
+];
+
+/// The glyph for a character, with anything the font doesn't have drawn as `?`.
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    let index = match c {
+        ' '..='`' => c as usize - ' ' as usize,
+        '{'..='~' => c as usize - '{' as usize + 65,
+        _ => '?' as usize - ' ' as usize,
+    };
+    GLYPHS[index]
+}
+
+/// Draws `text` with its top left corner at (`x`, `y`), setting lit pixels to `color`.
+/// Anything past the edges is left off.
+pub fn draw_text<R: AsMut<[u8]>>(canvas: &mut [R], x: usize, y: usize, text: &str, color: u8) {
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i * ADVANCE;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            let line = match canvas.get_mut(y + row) {
+                Some(line) => line.as_mut(),
+                None => break,
+            };
+            for column in 0..GLYPH_WIDTH {
+                if bits >> (GLYPH_WIDTH - 1 - column) & 1 == 1 {
+                    if let Some(pixel) = line.get_mut(left + column) {
+                        *pixel = color;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn letters_and_digits_are_distinct() {
+        let glyphs: Vec<_> = ('0'..='9').chain('A'..='Z').map(glyph).collect();
+        for (i, a) in glyphs.iter().enumerate() {
+            assert!(glyphs[i + 1..].iter().all(|b| a != b), "glyph {} is repeated", i);
+        }
+        assert_eq!(glyph('q'), glyph('Q'));
+        assert_eq!(glyph('é'), glyph('?'));
+    }
+}
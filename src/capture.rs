@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+use std::path::PathBuf;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::palette::Palette;
+
+/// A frame rendered to 8-bit RGB.
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>,
+}
+
+/// Colours a frame with the palette, with each CHIP-8 pixel becoming a `scale` by `scale` square.
+pub fn render<R: AsRef<[u8]>>(display: &[R], palette: &Palette, scale: u32) -> Image {
+    let scale = scale as usize;
+    let height = display.len() * scale;
+    let width = display.first().map_or(0, |row| row.as_ref().len()) * scale;
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for row in display {
+        let mut line = Vec::with_capacity(width * 3);
+        for &pixel in row.as_ref() {
+            let color = palette.color(pixel);
+            for _ in 0..scale {
+                line.extend_from_slice(&[color.0, color.1, color.2]);
+            }
+        }
+        for _ in 0..scale {
+            rgb.extend_from_slice(&line);
+        }
+    }
+
+    Image { width: width as u32, height: height as u32, rgb }
+}
+
+pub fn save_png(path: &Path, image: &Image) -> Result<(), Box<dyn Error>> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width, image.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&image.rgb)?;
+    Ok(())
+}
+
+/// A file name in the current directory from the current UTC time, like
+/// `chip8-2020-02-23-181502.png`, or `chip8-2020-02-23-181502-2.png` if that's already taken by a
+/// capture earlier in the same second.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+pub fn timestamped_path(prefix: &str, extension: &str) -> PathBuf {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+    let stem = format!("{}-{:04}-{:02}-{:02}-{:02}{:02}{:02}", prefix, year, month, day, time / 3600, time % 3600 / 60, time % 60);
+    unused_path(Path::new(""), &stem, extension)
+}
+
+// The first of `stem.extension`, `stem-2.extension`, `stem-3.extension`... that doesn't exist in `dir`
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn unused_path(dir: &Path, stem: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", stem, extension));
+    let mut count = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", stem, count, extension));
+        count += 1;
+    }
+    path
+}
+
+// Converts days since 1970-01-01 to a (year, month, day) date, from
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::palette::Rgb;
+
+    #[test]
+    fn render_scales_pixels() {
+        let palette = Palette { name: "test", colors: [Rgb(0, 0, 0), Rgb(255, 0, 0), Rgb(0, 255, 0), Rgb(0, 0, 255)] };
+        let image = render(&[[1u8, 0]], &palette, 2);
+        assert_eq!((image.width, image.height), (4, 2));
+        assert_eq!(&image.rgb[..12], &[255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&image.rgb[..12], &image.rgb[12..]);
+    }
+
+    #[test]
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    fn dates_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(18315), (2020, 2, 23));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    fn captures_in_the_same_second_get_a_count() {
+        let dir = std::env::temp_dir();
+        let stem = "chip8_capture_test";
+        std::fs::write(dir.join("chip8_capture_test.png"), b"").unwrap();
+        assert_eq!(unused_path(&dir, stem, "png"), dir.join("chip8_capture_test-2.png"));
+        assert_eq!(unused_path(&dir, stem, "gif"), dir.join("chip8_capture_test.gif"));
+        std::fs::remove_file(dir.join("chip8_capture_test.png")).unwrap();
+    }
+}
@@ -1,23 +1,87 @@
-use super::drivers::{DisplayDriver, InputDriver, AudioDriver};
 use super::instruction::{Instruction, OpCodeInstruction};
 use super::font::FONT_SET;
+use super::host::{Keypad, Screen};
+use super::platform::{Platform, Quirks, RngMode};
+use super::rng::{Rng, SeededRng};
 
-use rand;
-use rand::Rng;
-
-use std::thread;
-use std::time::Duration;
-
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
 
 use crate::CHIP8_WIDTH;
 use crate::CHIP8_HEIGHT;
 
-const MEMORY_SIZE: usize = 4 * 1024;
-const NUM_STACK_FRAMES: usize = 16;
+pub const MEMORY_SIZE: usize = 4 * 1024;
+pub const NUM_STACK_FRAMES: usize = 16; // how deep calls can nest
 const NUM_GENERAL_REGS: usize = 16;
-const NUM_KEYS: usize = 16;
+pub const NUM_KEYS: usize = 16;
+pub const PROGRAM_START: usize = 0x200;
+pub const MAX_PROGRAM_SIZE: usize = MEMORY_SIZE - PROGRAM_START; // anything past this doesn't fit in memory
+
+// The VIP interpreter lived in the first two pages of memory, and its RND read from the second
+pub const VIP_INTERPRETER_PAGE: usize = 0x100;
+
+const STATE_MAGIC: &[u8] = b"C8S\x01";
+const STATE_SIZE: usize = NUM_GENERAL_REGS + 3 * 8 + MEMORY_SIZE + NUM_STACK_FRAMES * 8 + 2
+	+ CHIP8_WIDTH * CHIP8_HEIGHT + NUM_KEYS + 8 + 2;
+/// How many bytes a save state takes, header included.
+pub const STATE_LEN: usize = STATE_MAGIC.len() + STATE_SIZE;
+
+/// Errors the interpreter can run into while executing a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Chip8Error {
+	InvalidOpcode { opcode: u16, addr: usize }, // opcode doesn't decode to any instruction
+	PcOutOfBounds(usize), // program counter ran off the end of memory
+	MemoryOutOfBounds(usize), // I register pointed past the end of memory
+	StackOverflow, // more than NUM_STACK_FRAMES nested calls
+	StackUnderflow, // RET with nothing on the stack
+}
+
+impl fmt::Display for Chip8Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Chip8Error::InvalidOpcode { opcode, addr } => write!(f, "invalid opcode {:04X} at {:#05X}", opcode, addr),
+			Chip8Error::PcOutOfBounds(addr) => write!(f, "program counter out of bounds at {:#05X}", addr),
+			Chip8Error::MemoryOutOfBounds(addr) => write!(f, "memory access out of bounds at {:#05X}", addr),
+			Chip8Error::StackOverflow => write!(f, "stack overflow"),
+			Chip8Error::StackUnderflow => write!(f, "return with an empty stack"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl Error for Chip8Error {}
+
+/// Reasons a save state can't be loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StateError {
+	NotAState, // doesn't start with the save state header
+	WrongSize(usize), // cut short, or from a different version
+	InvalidStackPointer(u64),
+	InvalidProgramCounter(u64), // past the end of memory
+	InvalidStackFrame(u64), // a return address past the end of memory
+}
 
-pub struct Chip8 {
+impl fmt::Display for StateError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StateError::NotAState => write!(f, "not a CHIP-8 save state"),
+			StateError::WrongSize(len) => write!(f, "save state is {} bytes, expected {}", len, STATE_LEN),
+			StateError::InvalidStackPointer(sp) => write!(f, "save state has stack pointer {}, past the end of the stack", sp),
+			StateError::InvalidProgramCounter(pc) => write!(f, "save state has program counter {:#X}, past the end of memory", pc),
+			StateError::InvalidStackFrame(addr) => write!(f, "save state has return address {:#X} on the stack, past the end of memory", addr),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl Error for StateError {}
+
+/// The machine: memory, registers, timers, display and keypad, all fixed size so it never allocates.
+/// `RND` takes its numbers from `R`, a seeded generator unless a host supplies its own.
+pub struct Chip8<R = SeededRng> {
 	regs: [u8; NUM_GENERAL_REGS],
 	i_reg: usize, // address register
 	sp: usize, // stack pointer
@@ -28,30 +92,45 @@ pub struct Chip8 {
 	sound_timer: u8,
 	display: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
 	keys: [bool; NUM_KEYS],
+	rng: R, // source of RND's numbers
+	platform: Platform,
+	quirks: Quirks,
+	vip_random: u8, // the VIP interpreter's last random number, which is also where it reads the next from
+	ticks: u8, // counts timer ticks, like the VIP's interrupt routine did
+	vip_interpreter: bool, // whether RND has the VIP interpreter's code to read
+	sound_timer_set: Option<u8>, // the last value the program set the sound timer to, until taken
 }
 
 impl Chip8 {
-	pub fn new(program: Vec<u8>) -> Chip8 {
+	/// Loads a program with a random seed for `RND`.
+	#[cfg(feature = "std")]
+	pub fn new<P: AsRef<[u8]>>(program: P) -> Chip8 {
+		Chip8::with_seed(program, rand::random())
+	}
+
+	/// Loads a program with a fixed seed for `RND`, so that the same inputs always give the same run.
+	pub fn with_seed<P: AsRef<[u8]>>(program: P, seed: u64) -> Chip8 {
+		Chip8::with_rng(program, SeededRng::new(seed))
+	}
+}
+
+impl<R: Rng> Chip8<R> {
+	/// Loads a program, taking `RND`'s numbers from `rng`.
+	pub fn with_rng<P: AsRef<[u8]>>(program: P, rng: R) -> Chip8<R> {
 		let mut memory = [0; MEMORY_SIZE];
 
 		for (i, byte) in FONT_SET.iter().enumerate() {
 			memory[i] = *byte;
 		}
 
-		for (i, &byte) in program.iter().enumerate() {
+		for (i, &byte) in program.as_ref().iter().enumerate() {
 			let addr = 0x200 + i;
 			if addr < 4096 {
 				memory[0x200 + i] = byte;
 			}
 		}
 
-		let mut display = [[0 as u8; CHIP8_WIDTH]; CHIP8_HEIGHT];
-
-		for y in 0..CHIP8_HEIGHT {
-			for x in 0..CHIP8_WIDTH {
-				display[y][x] = 0;
-			}
-		}
+		let display = [[0u8; CHIP8_WIDTH]; CHIP8_HEIGHT];
 
 		Chip8 {
 			regs: [0; NUM_GENERAL_REGS],
@@ -64,55 +143,239 @@ impl Chip8 {
 			stack: [0; NUM_STACK_FRAMES],
 			display,
 			keys: [false; NUM_KEYS],
+			rng,
+			platform: Platform::default(),
+			quirks: Platform::default().quirks(),
+			vip_random: 0,
+			ticks: 0,
+			vip_interpreter: false,
+			sound_timer_set: None,
 		 }
 	}
 
-	pub fn run(&mut self) {
-		let sdl_context = sdl2::init().unwrap();
-		let mut display_driver = DisplayDriver::new(&sdl_context);
-		let mut input_driver = InputDriver::new(&sdl_context);
-		let audio_driver = AudioDriver::new(&sdl_context);
+	/// Behaves like `platform`'s interpreter, with its quirks.
+	pub fn set_platform(&mut self, platform: Platform) {
+		self.platform = platform;
+		self.quirks = platform.quirks();
+	}
+
+	pub fn platform(&self) -> Platform {
+		self.platform
+	}
 
-		display_driver.draw(&self.display);
+	/// Overrides the platform's quirks. Call after `set_platform`.
+	pub fn set_quirks(&mut self, quirks: Quirks) {
+		self.quirks = quirks;
+	}
 
-		let mut instructions_executed = 0;
+	pub fn quirks(&self) -> Quirks {
+		self.quirks
+	}
 
-		loop { // fetch decode execute loop
+	/// Loads a dump of the VIP's CHIP-8 interpreter, whose code `RND` reads from on that platform.
+	/// Only its second page is used, which sits below programs and clear of the font.
+	pub fn load_vip_interpreter(&mut self, image: &[u8]) {
+		let page = image.iter().skip(VIP_INTERPRETER_PAGE).take(0x100);
+		for (byte, &code) in self.memory[VIP_INTERPRETER_PAGE..].iter_mut().zip(page) {
+			*byte = code;
+		}
+		self.vip_interpreter = true;
+	}
 
-			if let Ok(keys) = input_driver.process_inputs() {
-				self.keys = keys;
-			} else {
-				return;
-			}
+	/// Where `RND` gets its numbers from. The VIP's method needs its interpreter loaded: without the
+	/// code to read it would only return the tick counter, so the platform's seeded generator is used.
+	pub fn rng_mode(&self) -> RngMode {
+		match self.platform.rng_mode() {
+			RngMode::CosmacVip if !self.vip_interpreter => RngMode::Seeded,
+			mode => mode,
+		}
+	}
 
-			if instructions_executed > 8 { // 500 / 60 is ~ 8. 500 cycles per second, so this block is executed once every 1/60th of a second (hopefully)
-				if self.delay_timer > 0 {
-					self.delay_timer -= 1;
-				}
-				if self.sound_timer > 0 {
-					audio_driver.start_beep();
-					self.sound_timer -= 1;
-				} else {
-					audio_driver.stop_beep();
-				}
-				instructions_executed = 0;
+	/// Fetches, decodes and executes a single instruction.
+	pub fn cycle(&mut self) -> Result<(), Chip8Error> {
+		let instr = self.fetch_instruction()?;
+		self.execute_instruction(instr)
+	}
+
+	/// Counts the delay and sound timers down by one. Should be called at 60Hz.
+	pub fn tick_timers(&mut self) {
+		self.ticks = self.ticks.wrapping_add(1);
+		if self.delay_timer > 0 {
+			self.delay_timer -= 1;
+		}
+		if self.sound_timer > 0 {
+			self.sound_timer -= 1;
+		}
+	}
+
+	/// Runs a 60th of a second: reads the keypad, runs `instructions` instructions, ticks the timers
+	/// and shows the display. Gives what the program set the sound timer to, if it did.
+	pub fn run_frame<K: Keypad, S: Screen>(&mut self, instructions: u32, keypad: &mut K, screen: &mut S) -> Result<Option<u8>, Chip8Error> {
+		self.set_keys(keypad.keys());
+		for _ in 0..instructions {
+			self.cycle()?;
+		}
+		self.tick_timers();
+		screen.draw(&self.display);
+		Ok(self.take_sound_timer_set())
+	}
+
+	pub fn set_keys(&mut self, keys: [bool; NUM_KEYS]) {
+		self.keys = keys;
+	}
+
+	pub fn display(&self) -> &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT] {
+		&self.display
+	}
+
+	pub fn pc(&self) -> usize {
+		self.pc
+	}
+
+	pub fn i(&self) -> usize {
+		self.i_reg
+	}
+
+	pub fn registers(&self) -> &[u8; NUM_GENERAL_REGS] {
+		&self.regs
+	}
+
+	/// The opcode at the program counter, which the next cycle will run, if the PC is still in memory.
+	pub fn next_opcode(&self) -> Option<u16> {
+		match self.memory.get(self.pc..self.pc + 2) {
+			Some(&[high, low]) => Some(u16::from_be_bytes([high, low])),
+			_ => None,
+		}
+	}
+
+	pub fn sound_timer(&self) -> u8 {
+		self.sound_timer
+	}
+
+	/// The value the program last set the sound timer to, if it has since this was last called.
+	/// Audio that counts the timer down itself can start from this and stay exact to the sample.
+	pub fn take_sound_timer_set(&mut self) -> Option<u8> {
+		self.sound_timer_set.take()
+	}
+
+	/// A hash of everything that affects how the program runs from here on: registers, timers, memory,
+	/// stack, display, keys and RNG state. Two runs that agree on it are in step.
+	///
+	/// This is 64-bit FNV-1a over the same bytes as a save state, so the value is the same on every
+	/// platform and Rust version.
+	pub fn state_hash(&self) -> u64 {
+		let mut hash = Fnv1a::new();
+		self.write_state(|bytes| hash.write(bytes));
+		hash.finish()
+	}
+
+	/// Saves the whole machine, including the RNG, so `load_state` can carry on exactly where it left off.
+	#[cfg(feature = "std")]
+	pub fn save_state(&self) -> Vec<u8> {
+		let mut state = [0; STATE_LEN];
+		self.save_state_to(&mut state);
+		state.to_vec()
+	}
+
+	/// Saves the whole machine into `out`, like `save_state` but without allocating.
+	pub fn save_state_to(&self, out: &mut [u8; STATE_LEN]) {
+		let (magic, mut rest) = out.split_at_mut(STATE_MAGIC.len());
+		magic.copy_from_slice(STATE_MAGIC);
+		self.write_state(|bytes| {
+			let (written, tail) = core::mem::take(&mut rest).split_at_mut(bytes.len());
+			written.copy_from_slice(bytes);
+			rest = tail;
+		});
+	}
+
+	/// Restores a state from `save_state`. The machine is left as it was if the state is invalid.
+	pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+		if !state.starts_with(STATE_MAGIC) {
+			return Err(StateError::NotAState);
+		}
+		if state.len() != STATE_LEN {
+			return Err(StateError::WrongSize(state.len()));
+		}
+
+		// The interpreter indexes memory with pc and return addresses and the stack with sp, so these are
+		// checked before anything is overwritten. I is left alone: a program can move it past the end of
+		// memory, and every access through it is checked. The platform and quirks are settings rather
+		// than state and stay
+		let mut reader = StateReader(&state[STATE_MAGIC.len()..]);
+		reader.bytes(NUM_GENERAL_REGS);
+		reader.u64(); // I
+		let sp = reader.u64();
+		let pc = reader.u64();
+		reader.bytes(MEMORY_SIZE);
+		if sp > NUM_STACK_FRAMES as u64 {
+			return Err(StateError::InvalidStackPointer(sp));
+		}
+		if pc >= MEMORY_SIZE as u64 {
+			return Err(StateError::InvalidProgramCounter(pc));
+		}
+		for _ in 0..NUM_STACK_FRAMES {
+			let addr = reader.u64();
+			if addr >= MEMORY_SIZE as u64 {
+				return Err(StateError::InvalidStackFrame(addr));
 			}
+		}
 
-			let instr = self.fetch_instruction();
-			self.execute_instruction(instr);
-			display_driver.draw(&self.display);
+		let mut reader = StateReader(&state[STATE_MAGIC.len()..]);
+		self.regs.copy_from_slice(reader.bytes(NUM_GENERAL_REGS));
+		self.i_reg = reader.usize();
+		self.sp = reader.usize();
+		self.pc = reader.usize();
+		self.memory.copy_from_slice(reader.bytes(MEMORY_SIZE));
+		for frame in self.stack.iter_mut() {
+			*frame = reader.usize();
+		}
+		self.delay_timer = reader.bytes(1)[0];
+		self.sound_timer = reader.bytes(1)[0];
+		for row in self.display.iter_mut() {
+			row.copy_from_slice(reader.bytes(CHIP8_WIDTH));
+		}
+		for key in self.keys.iter_mut() {
+			*key = reader.bytes(1)[0] != 0;
+		}
+		self.rng.restore(reader.u64());
+		self.vip_random = reader.bytes(1)[0];
+		self.ticks = reader.bytes(1)[0];
+		self.sound_timer_set = None;
+		Ok(())
+	}
 
-			instructions_executed += 1;
-			thread::sleep(Duration::from_millis(2));
+	// Feeds every piece of machine state to `write` in a fixed order, with sizes as little endian u64s.
+	fn write_state<F: FnMut(&[u8])>(&self, mut write: F) {
+		write(&self.regs);
+		write(&(self.i_reg as u64).to_le_bytes());
+		write(&(self.sp as u64).to_le_bytes());
+		write(&(self.pc as u64).to_le_bytes());
+		write(&self.memory);
+		for frame in self.stack.iter() {
+			write(&(*frame as u64).to_le_bytes());
+		}
+		write(&[self.delay_timer, self.sound_timer]);
+		for row in self.display.iter() {
+			write(row);
 		}
+		for &key in self.keys.iter() {
+			write(&[key as u8]);
+		}
+		write(&self.rng.state().to_le_bytes());
+		write(&[self.vip_random, self.ticks]);
 	}
 
-	fn fetch_instruction(&self) -> Instruction {
+	fn fetch_instruction(&self) -> Result<Instruction, Chip8Error> {
+		if self.pc + 1 >= MEMORY_SIZE {
+			return Err(Chip8Error::PcOutOfBounds(self.pc));
+		}
 		let opcode = (self.memory[self.pc] as u16) << 8 | (self.memory[self.pc + 1] as u16);
-		OpCodeInstruction::new(opcode).process_opcode().unwrap()
+		OpCodeInstruction::new(opcode)
+			.process_opcode()
+			.ok_or(Chip8Error::InvalidOpcode { opcode, addr: self.pc })
 	}
 
-	fn execute_instruction(&mut self, instruction: Instruction) {
+	fn execute_instruction(&mut self, instruction: Instruction) -> Result<(), Chip8Error> {
 		match instruction {
 
 			// 00E0 - Clear Screen
@@ -127,6 +390,9 @@ impl Chip8 {
 
 			// 00EE - Return from subroutine
 			Instruction::RET() => {
+				if self.sp == 0 {
+					return Err(Chip8Error::StackUnderflow);
+				}
 				self.sp -= 1;
 				self.pc = self.stack[self.sp];
 				self.pc += 2;
@@ -139,6 +405,9 @@ impl Chip8 {
 
 			// 2NNN - Calls subroutine at NNN
 			Instruction::CALL(addr) => {
+				if self.sp == NUM_STACK_FRAMES {
+					return Err(Chip8Error::StackOverflow);
+				}
 				self.stack[self.sp] = self.pc;
 				self.sp += 1;
 				self.pc = addr as usize;
@@ -193,6 +462,7 @@ impl Chip8 {
 			Instruction::OR(reg1, reg2) => {
 				let value = self.get_register(reg1) | self.get_register(reg2);
 				self.set_register(reg1, value);
+				self.reset_vf_after_logic();
 				self.pc += 2;
 			},
 
@@ -200,6 +470,7 @@ impl Chip8 {
 			Instruction::AND(reg1, reg2) =>  {
 				let value = self.get_register(reg1) & self.get_register(reg2);
 				self.set_register(reg1, value);
+				self.reset_vf_after_logic();
 				self.pc += 2;
 			},
 
@@ -207,6 +478,7 @@ impl Chip8 {
 			Instruction::XOR(reg1, reg2) => {
 				let value = self.get_register(reg1) ^ self.get_register(reg2);
 				self.set_register(reg1, value);
+				self.reset_vf_after_logic();
 				self.pc += 2;
 			},
 
@@ -226,10 +498,11 @@ impl Chip8 {
 			},
 
 			// 8XY6 - Shifts value of reg right by one. VF set to least sig bit before shift.
-			Instruction::SHR(reg) => {
+			Instruction::SHR(reg, reg2) => {
+				let value = self.shift_source(reg, reg2);
+				self.set_register(reg, value >> 1);
 				// The result of an and with 0b1 is the state of the rightmost bit
-				self.set_register(0xF, self.get_register(reg) & 0b1);
-				self.set_register(reg, self.get_register(reg) >> 1);
+				self.set_register(0xF, value & 0b1);
 				self.pc += 2;
 			},
 
@@ -245,10 +518,11 @@ impl Chip8 {
 			},
 
 			// 8XYE - Shifts value of reg left by one. VF set to most sig bit before shift.
-			Instruction::SHL(reg) => {
+			Instruction::SHL(reg, reg2) => {
+				let value = self.shift_source(reg, reg2);
+				self.set_register(reg, value << 1);
 				// Moving a u8 right 7 will leave it as a binary 0/1 only
-				self.set_register(0xF, self.get_register(reg) >> 7);
-				self.set_register(reg, self.get_register(reg) << 1);
+				self.set_register(0xF, value >> 7);
 				self.pc += 2;
 			},
 
@@ -267,15 +541,19 @@ impl Chip8 {
 				self.pc += 2;
 			},
 
-			// BNNN - Jumps to address NNN plus V0
+			// BNNN - Jumps to address NNN plus V0 (or BXNN to XNN plus VX, with the quirk)
 			Instruction::JPV0(addr) => {
-				self.pc = (addr as usize) + (self.get_register(0) as usize);
+				let reg = if self.quirks.jump_uses_vx { (addr >> 8) as u8 } else { 0 };
+				self.pc = (addr as usize) + (self.get_register(reg) as usize);
 			},
 
 			// CXNN Sets VX to random number masked by NN
 			Instruction::RND(reg, nn) => {
-				let mut rng = rand::thread_rng();
-				self.set_register(reg, rng.gen::<u8>() & nn);
+				let random = match self.rng_mode() {
+					RngMode::Seeded => self.rng.next_u8(),
+					RngMode::CosmacVip => self.next_vip_random(),
+				};
+				self.set_register(reg, random & nn);
 				self.pc += 2;
 			},
 
@@ -283,12 +561,23 @@ impl Chip8 {
 			Instruction::DRW(reg1, reg2, num_bytes) => {
 				let x = self.get_register(reg1);
 				let y = self.get_register(reg2);
+				self.check_memory_range(num_bytes as usize)?;
 
+				// The starting position always wraps; the rest of the sprite wraps or is cut off by the quirk
+				let (x, y) = (x as usize % CHIP8_WIDTH, y as usize % CHIP8_HEIGHT);
 				for index in 0..num_bytes {
 					self.set_register(0xF, 0);
-					let y = (y + index) as usize % CHIP8_HEIGHT; // should wrap back to top of display?
+					let y = y + index as usize;
+					if y >= CHIP8_HEIGHT && !self.quirks.sprites_wrap {
+						break;
+					}
+					let y = y % CHIP8_HEIGHT;
 					for bit in 0..8 {
-						let x = (x as u16 + bit as u16) as usize % CHIP8_WIDTH; // should wrap back to start of line?
+						let x = x + bit as usize;
+						if x >= CHIP8_WIDTH && !self.quirks.sprites_wrap {
+							break;
+						}
+						let x = x % CHIP8_WIDTH;
 						let pixel_to_display = (self.memory[self.i_reg + index as usize] >> (7 - bit)) & 1; // gets the specific bit of the current byte we're looking at
 						self.set_register(0xF, self.get_register(0xF) | pixel_to_display & self.display[y][x]); // set register 15 if a collision is detected
 						self.display[y][x] ^= pixel_to_display;
@@ -299,7 +588,7 @@ impl Chip8 {
 
 			// EX9E - Skips next instruction if key in VX isn't pressed.
 			Instruction::SKP(reg) => {
-				if self.keys[self.get_register(reg) as usize]{
+				if self.is_key_pressed(self.get_register(reg)) {
 					self.pc += 4;
 				} else {
 					self.pc += 2;
@@ -308,7 +597,7 @@ impl Chip8 {
 
 			// EXA1 - Skips next instruction if key in VX isn't pressed.
 			Instruction::SKNP(reg) => {
-				if !self.keys[self.get_register(reg) as usize]{
+				if !self.is_key_pressed(self.get_register(reg)) {
 					self.pc += 4;
 				} else {
 					self.pc += 2;
@@ -322,18 +611,12 @@ impl Chip8 {
 			},
 
 			// FX0A - Key press is awaited, then stored in VX.
+			// The program counter is left alone until a key is down, so the instruction repeats each cycle.
 			Instruction::LDK(reg) => {
-				let pressed_key;
-				'keyloop: loop {
-					for key in self.keys.iter() {
-						if *key {
-							pressed_key = *key;
-							break 'keyloop;
-						}
-					}
+				if let Some(key) = self.keys.iter().position(|&pressed| pressed) {
+					self.set_register(reg, key as u8);
+					self.pc += 2;
 				}
-				self.set_register(reg, pressed_key as u8);
-				self.pc += 2;
 			},
 
 			// FX15 - Set delay timer to VX.
@@ -345,24 +628,26 @@ impl Chip8 {
 			// FX18 - Set sound timer to VX.
 			Instruction::LDST(reg) => {
 				self.sound_timer = self.get_register(reg);
+				self.sound_timer_set = Some(self.sound_timer);
 				self.pc += 2;
 			},
 
 			// FX1E - ADd VX to i_reg.
 			Instruction::ADDI(reg) => {
-				self.i_reg += self.get_register(reg) as usize;
+				self.i_reg = self.i_reg.wrapping_add(self.get_register(reg) as usize);
 				self.pc += 2;
 			},
 
 			// FX29 - Sets i_reg to location of sprite in VX.
 			Instruction::LDS(reg) => {
-				let sprite = self.get_register(reg);
-				self.i_reg = (sprite * 5) as usize;
+				let sprite = self.get_register(reg) & 0xF; // only the low nibble selects a font character
+				self.i_reg = sprite as usize * 5;
 				self.pc += 2;
 			},
 
 			// FX33 - Store binary-coded decimal representation of VX at i, i+1, and i+3.
 			Instruction::BCD(reg) => {
+				self.check_memory_range(3)?;
 				self.memory[self.i_reg] = self.get_register(reg) / 100;
 				self.memory[self.i_reg + 1] = (self.get_register(reg) % 100) / 10;
 				self.memory[self.i_reg + 2] = self.get_register(reg) % 10;
@@ -371,30 +656,367 @@ impl Chip8 {
 
 			// FX55 - Stores V0 through VX in memory starting at i_reg.
 			Instruction::SR(reg) => {
+				self.check_memory_range(reg as usize + 1)?;
 				for x in 0..=reg {
 					let value = self.get_register(x);
 					self.memory[self.i_reg + x as usize] = value;
 				}
-				self.i_reg += reg as usize + 1;
+				if self.quirks.load_store_increments_i {
+					self.i_reg = self.i_reg.wrapping_add(reg as usize + 1);
+				}
 				self.pc += 2;
 			},
 
 			// FX66 - Loads V0 through VX from memory starting at i_reg.
 			Instruction::LR(reg) => {
+				self.check_memory_range(reg as usize + 1)?;
 				for x in 0..=reg {
 					self.set_register(x, self.memory[self.i_reg + x as usize]);
 				}
-				self.i_reg += reg as usize + 1;
+				if self.quirks.load_store_increments_i {
+					self.i_reg = self.i_reg.wrapping_add(reg as usize + 1);
+				}
 				self.pc += 2;
 			},
 		}
+		Ok(())
 	}
 
 	fn get_register(&self, reg: u8) -> u8 {
-		self.regs[reg as usize] as u8
+		self.regs[reg as usize]
 	}
 
 	fn set_register(&mut self, reg: u8, value: u8) {
 		self.regs[reg as usize] = value;
 	}
-}
\ No newline at end of file
+
+	// The VIP interpreter added the byte of its own code at its last random number (plus one, in the second
+	// page) to a counter its 60Hz interrupt kept running, and kept the sum for next time. Code is far from
+	// uniform, and RND called in a tight loop sees the same counter, so some values turn up much more than others.
+	fn next_vip_random(&mut self) -> u8 {
+		let addr = VIP_INTERPRETER_PAGE + self.vip_random.wrapping_add(1) as usize;
+		self.vip_random = self.memory[addr].wrapping_add(self.ticks);
+		self.vip_random
+	}
+
+	// The register a shift reads from: VY on interpreters with the shift quirk, otherwise VX itself.
+	fn shift_source(&self, reg: u8, reg2: u8) -> u8 {
+		self.get_register(if self.quirks.shift_uses_vy { reg2 } else { reg })
+	}
+
+	fn reset_vf_after_logic(&mut self) {
+		if self.quirks.logic_resets_vf {
+			self.set_register(0xF, 0);
+		}
+	}
+
+	// Only the low nibble of VX is used to pick a key, as on the original interpreter.
+	fn is_key_pressed(&self, key: u8) -> bool {
+		self.keys[(key & 0xF) as usize]
+	}
+
+	// Checks that `len` bytes starting at the I register all fall inside memory.
+	fn check_memory_range(&self, len: usize) -> Result<(), Chip8Error> {
+		match self.i_reg.checked_add(len) {
+			Some(end) if end <= MEMORY_SIZE => Ok(()),
+			_ => Err(Chip8Error::MemoryOutOfBounds(self.i_reg)),
+		}
+	}
+}
+
+struct Fnv1a(u64);
+
+impl Fnv1a {
+	fn new() -> Fnv1a {
+		Fnv1a(0xCBF2_9CE4_8422_2325)
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		for &byte in bytes {
+			self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01B3);
+		}
+	}
+
+	fn finish(&self) -> u64 {
+		self.0
+	}
+}
+
+// Reads a save state back in the order `write_state` wrote it. The length is checked up front.
+struct StateReader<'a>(&'a [u8]);
+
+impl<'a> StateReader<'a> {
+	fn bytes(&mut self, len: usize) -> &'a [u8] {
+		let (bytes, rest) = self.0.split_at(len);
+		self.0 = rest;
+		bytes
+	}
+
+	fn u64(&mut self) -> u64 {
+		let mut bytes = [0; 8];
+		bytes.copy_from_slice(self.bytes(8));
+		u64::from_le_bytes(bytes)
+	}
+
+	// Only for values already checked to be within memory or the stack
+	fn usize(&mut self) -> usize {
+		self.u64() as usize
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn invalid_opcode_is_reported() {
+		let mut chip8 = Chip8::with_seed(vec![0xFF, 0xFF], 0);
+		assert_eq!(chip8.cycle(), Err(Chip8Error::InvalidOpcode { opcode: 0xFFFF, addr: 0x200 }));
+	}
+
+	#[test]
+	fn return_with_empty_stack() {
+		let mut chip8 = Chip8::with_seed(vec![0x00, 0xEE], 0);
+		assert_eq!(chip8.cycle(), Err(Chip8Error::StackUnderflow));
+	}
+
+	#[test]
+	fn recursive_call_overflows_stack() {
+		let mut chip8 = Chip8::with_seed(vec![0x22, 0x00], 0); // CALL 0x200
+		for _ in 0..NUM_STACK_FRAMES {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		assert_eq!(chip8.cycle(), Err(Chip8Error::StackOverflow));
+	}
+
+	#[test]
+	fn store_registers_past_end_of_memory() {
+		let mut chip8 = Chip8::with_seed(vec![0xAF, 0xFF, 0xF3, 0x55], 0); // LDI 0xFFF, SR V3
+		assert_eq!(chip8.cycle(), Ok(()));
+		assert_eq!(chip8.cycle(), Err(Chip8Error::MemoryOutOfBounds(0xFFF)));
+	}
+
+	#[test]
+	fn running_off_end_of_memory() {
+		let mut chip8 = Chip8::with_seed(vec![0x1F, 0xFF], 0); // JMP 0xFFF
+		assert_eq!(chip8.cycle(), Ok(()));
+		assert_eq!(chip8.cycle(), Err(Chip8Error::PcOutOfBounds(0xFFF)));
+	}
+
+	#[test]
+	fn wait_for_key_stores_key_index() {
+		let mut chip8 = Chip8::with_seed(vec![0xF5, 0x0A], 0); // LDK V5
+		assert_eq!(chip8.cycle(), Ok(()));
+		assert_eq!(chip8.pc, 0x200);
+
+		let mut keys = [false; NUM_KEYS];
+		keys[0xB] = true;
+		chip8.set_keys(keys);
+		assert_eq!(chip8.cycle(), Ok(()));
+		assert_eq!(chip8.get_register(5), 0xB);
+		assert_eq!(chip8.pc, 0x202);
+	}
+
+	#[test]
+	fn same_seed_same_run() {
+		let program = vec![0xC0, 0xFF, 0xC1, 0xFF, 0x12, 0x00]; // RND V0 0xFF, RND V1 0xFF, JMP 0x200
+		let mut a = Chip8::with_seed(program.clone(), 7);
+		let mut b = Chip8::with_seed(program, 7);
+		for _ in 0..30 {
+			assert_eq!(a.cycle(), Ok(()));
+			assert_eq!(b.cycle(), Ok(()));
+			assert_eq!(a.state_hash(), b.state_hash());
+		}
+
+		b.set_keys([true; NUM_KEYS]);
+		assert_ne!(a.state_hash(), b.state_hash());
+	}
+
+	#[test]
+	fn loaded_state_repeats_the_run() {
+		let program = vec![0xC0, 0xFF, 0x60, 0x00, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x00]; // RND V0, LD V0 0, LDS V0, DRW V1 V1 5, JMP 0x200
+		let mut chip8 = Chip8::with_seed(program, 3);
+		for _ in 0..7 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		let mut state = [0; STATE_LEN];
+		chip8.save_state_to(&mut state);
+		let saved_hash = chip8.state_hash();
+		for _ in 0..20 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		let later_hash = chip8.state_hash();
+
+		assert_eq!(chip8.load_state(&state), Ok(()));
+		assert_eq!(chip8.state_hash(), saved_hash);
+		for _ in 0..20 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		assert_eq!(chip8.state_hash(), later_hash);
+
+		assert_eq!(chip8.load_state(&state[..100]), Err(StateError::WrongSize(100)));
+		assert_eq!(chip8.load_state(b"nonsense"), Err(StateError::NotAState));
+		assert_eq!(chip8.state_hash(), later_hash);
+	}
+
+	#[test]
+	fn state_with_i_past_memory_round_trips() {
+		let program = vec![0xAF, 0xFF, 0x60, 0x05, 0xF0, 0x1E, 0xF0, 0x55]; // LD I 0xFFF, LD V0 5, ADD I V0, LD [I] V0
+		let mut chip8 = Chip8::with_seed(program.clone(), 0);
+		for _ in 0..3 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		assert_eq!(chip8.i_reg, 0x1004);
+		let mut state = [0; STATE_LEN];
+		chip8.save_state_to(&mut state);
+
+		let mut loaded = Chip8::with_seed(program, 0);
+		assert_eq!(loaded.load_state(&state), Ok(()));
+		assert_eq!(loaded.state_hash(), chip8.state_hash());
+		assert_eq!(loaded.cycle(), Err(Chip8Error::MemoryOutOfBounds(0x1004)));
+
+		assert_eq!(load_corrupted(STATE_I, u64::MAX), Ok(()));
+	}
+
+	// Where I, sp, pc and the stack start in a save state, after the header and registers
+	const STATE_I: usize = STATE_MAGIC.len() + NUM_GENERAL_REGS;
+	const STATE_SP: usize = STATE_I + 8;
+	const STATE_PC: usize = STATE_SP + 8;
+	const STATE_STACK: usize = STATE_PC + 8 + MEMORY_SIZE;
+
+	// Loads a state with one value overwritten, checking a rejected state leaves the machine alone.
+	fn load_corrupted(offset: usize, value: u64) -> Result<(), StateError> {
+		let mut chip8 = Chip8::with_seed(vec![0x12, 0x00], 0); // JMP 0x200
+		let mut state = [0; STATE_LEN];
+		chip8.save_state_to(&mut state);
+		let hash = chip8.state_hash();
+		state[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+		let result = chip8.load_state(&state);
+		if result.is_err() {
+			assert_eq!(chip8.state_hash(), hash);
+		}
+		result
+	}
+
+	#[test]
+	fn state_with_stack_pointer_past_the_stack_is_rejected() {
+		assert_eq!(load_corrupted(STATE_SP, 17), Err(StateError::InvalidStackPointer(17)));
+		assert_eq!(load_corrupted(STATE_SP, 16), Ok(()));
+	}
+
+	#[test]
+	fn state_with_program_counter_past_memory_is_rejected() {
+		assert_eq!(load_corrupted(STATE_PC, 0x1000), Err(StateError::InvalidProgramCounter(0x1000)));
+		assert_eq!(load_corrupted(STATE_PC, u64::MAX), Err(StateError::InvalidProgramCounter(u64::MAX)));
+		assert_eq!(load_corrupted(STATE_PC, 0xFFE), Ok(()));
+	}
+
+
+	#[test]
+	fn state_with_return_address_past_memory_is_rejected() {
+		assert_eq!(load_corrupted(STATE_STACK, u64::MAX), Err(StateError::InvalidStackFrame(u64::MAX)));
+		assert_eq!(load_corrupted(STATE_STACK + 15 * 8, 0x1000), Err(StateError::InvalidStackFrame(0x1000)));
+	}
+
+	#[test]
+	fn vip_random_reads_interpreter_and_ticks() {
+		let mut chip8 = Chip8::with_seed(vec![0xC0, 0xFF, 0xC1, 0x0F], 0); // RND V0 0xFF, RND V1 0x0F
+		chip8.set_platform(Platform::CosmacVip);
+		let mut interpreter = vec![0; 0x200];
+		interpreter[0x101] = 0x40;
+		interpreter[0x145] = 0x2B;
+		chip8.load_vip_interpreter(&interpreter);
+		for _ in 0..4 {
+			chip8.tick_timers();
+		}
+
+		assert_eq!(chip8.cycle(), Ok(()));
+		assert_eq!(chip8.get_register(0), 0x44);
+		assert_eq!(chip8.cycle(), Ok(()));
+		assert_eq!(chip8.get_register(1), 0x0F); // 0x2B + 4, masked
+	}
+
+	#[test]
+	fn vip_random_without_interpreter_is_seeded() {
+		let program = vec![0xC0, 0xFF, 0xC1, 0xFF]; // RND V0 0xFF, RND V1 0xFF
+		let mut vip = Chip8::with_seed(program.clone(), 5);
+		vip.set_platform(Platform::CosmacVip);
+		assert_eq!(vip.rng_mode(), RngMode::Seeded);
+		let mut seeded = Chip8::with_seed(program, 5);
+		for _ in 0..2 {
+			assert_eq!(vip.cycle(), Ok(()));
+			assert_eq!(seeded.cycle(), Ok(()));
+		}
+		assert_eq!(vip.registers(), seeded.registers());
+
+		vip.load_vip_interpreter(&[]);
+		assert_eq!(vip.rng_mode(), RngMode::CosmacVip);
+	}
+
+	#[test]
+	fn vip_quirks() {
+		// LD VF 1, LD V1 0x81, SHR V0 V1, OR V2 V3, LDI 0x300, SR V1
+		let program = vec![0x6F, 0x01, 0x61, 0x81, 0x80, 0x16, 0x82, 0x31, 0xA3, 0x00, 0xF1, 0x55];
+		let mut chip8 = Chip8::with_seed(program.clone(), 0);
+		for _ in 0..6 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		assert_eq!(chip8.get_register(0), 0);
+		assert_eq!(chip8.get_register(0xF), 0); // the low bit of V0
+		assert_eq!(chip8.i_reg, 0x302);
+
+		let mut chip8 = Chip8::with_seed(program, 0);
+		chip8.set_platform(Platform::CosmacVip);
+		chip8.set_quirks(Quirks { load_store_increments_i: false, ..chip8.quirks() });
+		for _ in 0..3 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		assert_eq!(chip8.get_register(0), 0x40); // V1 shifted
+		assert_eq!(chip8.get_register(0xF), 1);
+		chip8.set_register(0xF, 1);
+		for _ in 0..3 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		assert_eq!(chip8.get_register(0xF), 0); // reset by OR
+		assert_eq!(chip8.i_reg, 0x300);
+	}
+
+	#[test]
+	fn frame_reads_keypad_and_draws_screen() {
+		struct HeldKey(usize);
+		impl Keypad for HeldKey {
+			fn keys(&mut self) -> [bool; NUM_KEYS] {
+				let mut keys = [false; NUM_KEYS];
+				keys[self.0] = true;
+				keys
+			}
+		}
+		struct LitPixels(usize);
+		impl Screen for LitPixels {
+			fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+				self.0 = display.iter().flatten().filter(|&&pixel| pixel != 0).count();
+			}
+		}
+
+		let program = vec![0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0xF0, 0x18]; // LDK V0, LDS V0, DRW V1 V1 5, LDST V0
+		let mut chip8 = Chip8::with_seed(program, 0);
+		let mut screen = LitPixels(0);
+		assert_eq!(chip8.run_frame(4, &mut HeldKey(0x7), &mut screen), Ok(Some(0x7)));
+		let seven = FONT_SET[7 * 5..8 * 5].iter().map(|row| row.count_ones() as usize).sum();
+		assert_eq!(screen.0, seven);
+	}
+
+	#[test]
+	fn clipped_sprites() {
+		let program = vec![0x60, 0x3C, 0xF0, 0x29, 0xD0, 0x05]; // LD V0 60, LDS V0, DRW V0 V0 5
+		let mut chip8 = Chip8::with_seed(program, 0);
+		chip8.set_quirks(Quirks { sprites_wrap: false, ..chip8.quirks() });
+		for _ in 0..3 {
+			assert_eq!(chip8.cycle(), Ok(()));
+		}
+		// At (60, 28) the font's C just fits across, but the last of its 5 rows is past the bottom
+		assert_eq!(&chip8.display[28][60..], &[1, 1, 1, 1]);
+		assert_eq!(&chip8.display[31][60..], &[1, 0, 0, 0]);
+		assert!(chip8.display[0].iter().all(|&pixel| pixel == 0));
+	}
+}
@@ -0,0 +1,619 @@
+use std::path::PathBuf;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+use std::str::FromStr;
+
+use crate::config::Profile;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+use crate::frontend::PHOSPHOR_DECAYS;
+use crate::palette::{Palette, Rgb};
+
+const DEFAULT_SCREENSHOT_SCALE: u32 = 10; // image pixels per CHIP-8 pixel
+const DEFAULT_TRACE_INSTRUCTIONS: u64 = 1000;
+const DEFAULT_TEST_FRAMES: u64 = 600; // ten seconds
+
+pub const HELP: &str = "\
+A CHIP-8 emulator.
+
+Usage: chip_8 [COMMAND] [OPTIONS] <ROM>
+
+Commands:
+  run      Play a ROM (the default when no command is given), or pick one from the ROMs in a
+           directory when none is given
+  disasm   List a ROM's instructions
+  trace    Run a ROM headless, printing every instruction executed and the registers before it
+  info     Show a ROM's size, SHA-1 and the settings it would run with
+  test     Run a ROM headless and print a hash of the display, optionally checking it
+  lint     Look through a ROM for common bugs without running it
+  cfg      Draw a ROM's control flow graph, for Graphviz
+  help     Show this, or with a command, that command's options
+
+Run 'chip_8 <COMMAND> --help' for a command's options.";
+
+const RUN_HELP: &str = "\
+Play a ROM.
+
+Usage: chip_8 [run] [OPTIONS] [ROM]
+
+With no ROM, a browser lists the ROMs in ROMS, or the directory set with rom_dir in the config
+file, and F1 returns to it from a game.
+
+Emulation:
+  --speed N                  Instructions per frame, 8 by default
+  --platform chip8|vip       Interpreter to behave like
+  --quirk NAME               Turn a quirk on, whatever the platform does
+  --no-quirk NAME            Turn a quirk off
+  --seed N                   Seed for RND, so a run can be repeated
+  --vip-interpreter FILE     Dump of the COSMAC VIP interpreter, which its RND needs
+  --config FILE              Config file to read instead of the default one
+  --rom-dir DIR              Directory for the ROM browser to list
+
+Display:
+  --frontend sdl|tui         Draw in a window or in the terminal
+  --color                    Use the palette's colours in the terminal
+  --palette NAME             classic, green, amber, lcd, octo, high-contrast or colorblind
+  --fg RRGGBB, --bg RRGGBB   Replace the foreground or background colour
+  --colors C0,C1,C2,C3       Replace all four colours
+  --scale N                  Window pixels per CHIP-8 pixel, 20 by default
+  --integer-scaling          Only scale the picture by whole multiples
+  --phosphor                 Fade pixels out over a few frames
+  --phosphor-decay D         How much of a pixel is left after a frame, from 0 up to 1
+  --stats                    Show the frame rate and speed on screen
+
+Sound and input:
+  --beep-frequency HZ        Pitch of the beeper, 300 by default
+  --beep-waveform WAVE       square, sine, triangle or noise
+  --beep-duty D              Fraction of each cycle a square wave is high
+  --volume V                 From 0 to 1
+  --mute                     Start muted
+  --fast-forward N           How many times faster holding Tab runs, 4 by default
+  --fast-forward-sound S     mute, or pitch to speed the beeps up with the game
+  --keymap PRESET            qwerty, azerty, qwertz, dvorak or numpad
+
+Capture:
+  --screenshot-scale N       Image pixels per CHIP-8 pixel for screenshots, 10 by default
+  --screenshot-at-frame N FILE  Run headless and save a screenshot after N frames
+  --gif-scale N              Upscale GIF recordings
+  --record-video FILE.y4m    Write every frame as video
+  --record-audio FILE.wav    Write the beeper's output
+  --video-scale N            Upscale the video
+  --headless --frames N      Run N frames without a frontend, as fast as possible
+  --record FILE.c8m          Record the keypad to a movie
+  --play FILE.c8m            Play a movie back
+  --profile FILE             Count what the program runs, and write a report here at the end
+
+Quirks: shift_uses_vy, load_store_increments_i, jump_uses_vx, logic_resets_vf, sprites_wrap";
+
+const DISASM_HELP: &str = "\
+List a ROM's instructions, one per two bytes from where it's loaded at 0x200.
+Words that aren't instructions, usually sprite data, are shown as '??'.
+
+Usage: chip_8 disasm <ROM>";
+
+const TRACE_HELP: &str = "\
+Run a ROM headless with no keys pressed, printing every instruction executed along with I and
+V0 to VF before it runs.
+
+Usage: chip_8 trace [OPTIONS] <ROM>
+
+Options:
+  --instructions N           Stop after this many, 1000 by default
+  --speed N, --platform chip8|vip, --quirk NAME, --no-quirk NAME, --seed N,
+  --vip-interpreter FILE, --config FILE
+                             As for 'run'";
+
+const INFO_HELP: &str = "\
+Show a ROM's size and SHA-1, how much of it decodes as instructions, and the settings it would
+run with, after the config file and any options given.
+
+Usage: chip_8 info [OPTIONS] <ROM>
+
+Options:
+  --speed N, --platform chip8|vip, --quirk NAME, --no-quirk NAME, --config FILE
+                             As for 'run'";
+
+const TEST_HELP: &str = "\
+Run a ROM headless and print the SHA-1 of the display at the end, to check a ROM still draws
+the same thing. With --expect, a different hash is an error.
+
+Usage: chip_8 test [OPTIONS] <ROM>
+
+Options:
+  --frames N                 Frames to run, 600 by default, or to the end of a movie
+  --expect HASH              Exit with an error unless the display hash is this
+  --play FILE.c8m            Press keys from a movie, checking it stays in sync
+  --profile FILE             Count what the program runs, and write a report here at the end
+  --speed N, --platform chip8|vip, --quirk NAME, --no-quirk NAME, --seed N,
+  --vip-interpreter FILE, --config FILE
+                             As for 'run'";
+
+const LINT_HELP: &str = "\
+Look through everything a ROM can reach from 0x200 for common bugs, without running it:
+instructions that don't decode; jumps and calls into the middle of an instruction, into data
+or past the end of the program or memory; calls nested deeper than the 16 the stack holds; RET
+with no CALL to return to; and LD [I], VX or LD VX, [I] with I possibly too high. Instructions
+that depend on a quirk, and jumps to odd addresses, are noted too. Exits with an error if
+anything but notes is found.
+
+Usage: chip_8 lint <ROM>";
+
+const CFG_HELP: &str = "\
+Split everything a ROM can reach from 0x200 into basic blocks, and write them as a Graphviz DOT
+graph with each block's instructions disassembled. Edges are jumps, calls, returns and skips;
+JP V0 depends on a register at run time, so its edges go to an unresolved node.
+
+Usage: chip_8 cfg [OPTIONS] <ROM>
+
+Options:
+  -o, --output FILE          Write the graph here rather than to stdout
+
+Render it with, for example, 'dot -Tsvg rom.dot -o rom.svg'.";
+
+// Options that change how the machine runs, taken by every command that runs one.
+const MACHINE_OPTIONS: [&str; 7] = ["--speed", "--platform", "--quirk", "--no-quirk", "--seed", "--vip-interpreter", "--config"];
+
+/// What to do with the ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Subcommand {
+    Run,
+    Disasm,
+    Trace,
+    Info,
+    Test,
+    Lint,
+    Cfg,
+}
+
+impl Subcommand {
+    fn from_name(name: &str) -> Option<Subcommand> {
+        match name {
+            "run" => Some(Subcommand::Run),
+            "disasm" => Some(Subcommand::Disasm),
+            "trace" => Some(Subcommand::Trace),
+            "info" => Some(Subcommand::Info),
+            "test" => Some(Subcommand::Test),
+            "lint" => Some(Subcommand::Lint),
+            "cfg" => Some(Subcommand::Cfg),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Subcommand::Run => "run",
+            Subcommand::Disasm => "disasm",
+            Subcommand::Trace => "trace",
+            Subcommand::Info => "info",
+            Subcommand::Test => "test",
+            Subcommand::Lint => "lint",
+            Subcommand::Cfg => "cfg",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Subcommand::Run => RUN_HELP,
+            Subcommand::Disasm => DISASM_HELP,
+            Subcommand::Trace => TRACE_HELP,
+            Subcommand::Info => INFO_HELP,
+            Subcommand::Test => TEST_HELP,
+            Subcommand::Lint => LINT_HELP,
+            Subcommand::Cfg => CFG_HELP,
+        }
+    }
+
+    fn accepts(self, option: &str) -> bool {
+        match self {
+            Subcommand::Run => !["--instructions", "--expect", "--output", "-o"].contains(&option),
+            Subcommand::Disasm | Subcommand::Lint => false,
+            Subcommand::Trace => MACHINE_OPTIONS.contains(&option) || option == "--instructions",
+            Subcommand::Info => MACHINE_OPTIONS.contains(&option) && option != "--seed" && option != "--vip-interpreter",
+            Subcommand::Test => MACHINE_OPTIONS.contains(&option) || ["--frames", "--expect", "--play", "--profile"].contains(&option),
+            Subcommand::Cfg => option == "--output" || option == "-o",
+        }
+    }
+}
+
+/// A parsed command line.
+#[derive(Debug)]
+pub enum Command {
+    Run(Options),
+    Disasm(Options),
+    Trace(Options, u64), // instructions to trace
+    Info(Options),
+    Test(Options, Option<String>), // display hash to expect
+    Lint(Options),
+    Cfg(Options),
+    Help(&'static str), // print this and stop
+}
+
+/// Which frontend draws the display and reads the keypad.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontendKind {
+    Sdl,
+    Tui,
+}
+
+#[cfg(any(feature = "sdl", feature = "tui"))]
+impl FromStr for FrontendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sdl" => Ok(FrontendKind::Sdl),
+            "tui" => Ok(FrontendKind::Tui),
+            _ => Err(format!("unknown frontend '{}', expected 'sdl' or 'tui'", s)),
+        }
+    }
+}
+
+#[cfg(any(feature = "sdl", feature = "tui"))]
+impl Default for FrontendKind {
+    fn default() -> Self {
+        if cfg!(feature = "sdl") {
+            FrontendKind::Sdl
+        } else {
+            FrontendKind::Tui
+        }
+    }
+}
+
+/// Options for playing in a frontend, which a build without one doesn't take.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+#[derive(Debug)]
+pub struct FrontendOptions {
+    pub kind: FrontendKind,
+    #[cfg(feature = "tui")]
+    pub color: bool, // use colours in the terminal frontend
+    #[cfg(feature = "sdl")]
+    pub integer_scaling: bool,
+    pub phosphor: bool,
+    pub phosphor_decay: f32,
+    pub stats: bool, // start with the frame rate and speed on screen
+    pub gif_scale: u32,
+    pub keymap: Option<String>, // keymap preset, overriding the config file's
+    pub rom_dir: Option<PathBuf>, // where the ROM browser looks, overriding the config file
+}
+
+#[cfg(any(feature = "sdl", feature = "tui"))]
+impl Default for FrontendOptions {
+    fn default() -> Self {
+        FrontendOptions {
+            kind: FrontendKind::default(),
+            #[cfg(feature = "tui")]
+            color: false,
+            #[cfg(feature = "sdl")]
+            integer_scaling: false,
+            phosphor: false,
+            phosphor_decay: PHOSPHOR_DECAYS[1],
+            stats: false,
+            gif_scale: 1,
+            keymap: None,
+            rom_dir: None,
+        }
+    }
+}
+
+#[cfg(any(feature = "sdl", feature = "tui"))]
+impl FrontendOptions {
+    // Takes `arg` and its value from `args` if it's one of these options, returning whether it was.
+    fn parse_arg<I: Iterator<Item = String>>(&mut self, arg: &str, args: &mut I) -> Result<bool, String> {
+        match arg {
+            "--frontend" => {
+                let value = args.next().ok_or("--frontend needs a value")?;
+                self.kind = value.parse()?;
+            },
+            #[cfg(feature = "tui")]
+            "--color" => self.color = true,
+            #[cfg(feature = "sdl")]
+            "--integer-scaling" => self.integer_scaling = true,
+            "--phosphor" => self.phosphor = true,
+            "--phosphor-decay" => {
+                let value = args.next().ok_or("--phosphor-decay needs a value")?;
+                self.phosphor_decay = value.parse().ok().filter(|decay| (0.0..1.0).contains(decay))
+                    .ok_or_else(|| format!("'{}' isn't a valid decay, expected a number from 0 up to 1", value))?;
+                self.phosphor = true;
+            },
+            "--stats" => self.stats = true,
+            "--gif-scale" => self.gif_scale = parse_count(args.next(), "--gif-scale")?,
+            "--keymap" => self.keymap = Some(args.next().ok_or("--keymap needs a preset name")?),
+            "--rom-dir" => self.rom_dir = Some(PathBuf::from(args.next().ok_or("--rom-dir needs a directory")?)),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub rom: Option<String>, // only `run` goes without, opening the ROM browser
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    pub frontend: FrontendOptions,
+    pub profile: Profile, // settings that override the config file's: speed, platform, palette, scale, fast-forward and audio
+    pub colors: [Option<Rgb>; 4], // replace single colours of the palette
+    pub screenshot_scale: u32,
+    pub screenshot_at_frame: Option<(u64, PathBuf)>, // save a screenshot after this many frames
+    pub record_video: Option<PathBuf>,
+    pub record_audio: Option<PathBuf>,
+    pub video_scale: u32,
+    pub headless_frames: Option<u64>, // run this many frames with no frontend, as fast as possible
+    pub config: Option<PathBuf>, // config file to use instead of the default one
+    pub record_movie: Option<PathBuf>,
+    pub play_movie: Option<PathBuf>,
+    pub seed: Option<u64>, // seed for RND, random if not given
+    pub vip_interpreter: Option<PathBuf>, // dump of the VIP's interpreter, for its RND
+    pub output: Option<PathBuf>, // where cfg writes the graph, stdout if not given
+    pub profile_report: Option<PathBuf>, // profile the run and write the report here
+}
+
+impl Options {
+    /// The ROM given on the command line, which every command but `run` needs.
+    pub fn rom(&self) -> &str {
+        self.rom.as_deref().expect("the command line was checked for a ROM")
+    }
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut rom = None;
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    let mut frontend = FrontendOptions::default();
+    let mut profile = Profile::default();
+    let mut colors: [Option<Rgb>; 4] = [None; 4];
+    let mut screenshot_scale = DEFAULT_SCREENSHOT_SCALE;
+    let mut screenshot_at_frame = None;
+    let mut record_video = None;
+    let mut record_audio = None;
+    let mut video_scale = 1;
+    let mut headless = false;
+    let mut frames: Option<u64> = None;
+    let mut config = None;
+    let mut record_movie = None;
+    let mut play_movie = None;
+    let mut seed = None;
+    let mut vip_interpreter = None;
+    let mut instructions = DEFAULT_TRACE_INSTRUCTIONS;
+    let mut expect = None;
+    let mut output = None;
+    let mut profile_report = None;
+
+    let mut args = args.peekable();
+    let subcommand = match args.peek().map(String::as_str) {
+        Some("help") | Some("--help") | Some("-h") => {
+            args.next();
+            let help = args.next().map(|name| {
+                Subcommand::from_name(&name).map(Subcommand::help).ok_or_else(|| format!("unknown command '{}'", name))
+            });
+            return help.unwrap_or(Ok(HELP)).map(Command::Help);
+        },
+        Some(name) => match Subcommand::from_name(name) {
+            Some(subcommand) => {
+                args.next();
+                subcommand
+            },
+            None => Subcommand::Run,
+        },
+        None => Subcommand::Run,
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Ok(Command::Help(subcommand.help()));
+        }
+        if (arg.starts_with("--") || arg == "-o") && !subcommand.accepts(&arg) {
+            return Err(format!("{} doesn't take {}", subcommand.name(), arg));
+        }
+        #[cfg(any(feature = "sdl", feature = "tui"))]
+        if frontend.parse_arg(&arg, &mut args)? {
+            continue;
+        }
+
+        match arg.as_str() {
+            "--palette" => {
+                let name = args.next().ok_or("--palette needs a value")?;
+                Palette::preset(&name).ok_or_else(|| format!("unknown palette '{}'", name))?;
+                profile.palette = Some(name);
+            },
+            "--bg" => colors[0] = Some(args.next().ok_or("--bg needs a colour")?.parse()?),
+            "--fg" => colors[1] = Some(args.next().ok_or("--fg needs a colour")?.parse()?),
+            "--colors" => {
+                let value = args.next().ok_or("--colors needs four colours")?;
+                let parsed = value.split(',').map(str::parse).collect::<Result<Vec<Rgb>, _>>()?;
+                if parsed.len() != 4 {
+                    return Err("--colors needs four comma separated colours".to_string());
+                }
+                for (custom, &color) in colors.iter_mut().zip(parsed.iter()) {
+                    *custom = Some(color);
+                }
+            },
+            "--scale" => profile.scale = Some(parse_count(args.next(), "--scale")?),
+            "--speed" => profile.speed = Some(parse_count(args.next(), "--speed")?),
+            "--screenshot-scale" => screenshot_scale = parse_count(args.next(), "--screenshot-scale")?,
+            "--screenshot-at-frame" => {
+                let frame = args.next().ok_or("--screenshot-at-frame needs a frame number and a file")?;
+                let frame = frame.parse().map_err(|_| format!("'{}' isn't a frame number", frame))?;
+                let path = args.next().ok_or("--screenshot-at-frame needs a file to save to")?;
+                screenshot_at_frame = Some((frame, PathBuf::from(path)));
+            },
+            "--record-video" => record_video = Some(PathBuf::from(args.next().ok_or("--record-video needs a file")?)),
+            "--record-audio" => record_audio = Some(PathBuf::from(args.next().ok_or("--record-audio needs a file")?)),
+            "--video-scale" => video_scale = parse_count(args.next(), "--video-scale")?,
+            "--headless" => headless = true,
+            "--frames" => {
+                let value = args.next().ok_or("--frames needs a value")?;
+                frames = Some(value.parse().map_err(|_| format!("'{}' isn't a frame count", value))?);
+            },
+            "--config" => config = Some(PathBuf::from(args.next().ok_or("--config needs a file")?)),
+            "--record" => record_movie = Some(PathBuf::from(args.next().ok_or("--record needs a movie file")?)),
+            "--seed" => {
+                let value = args.next().ok_or("--seed needs a value")?;
+                seed = Some(value.parse().map_err(|_| format!("'{}' isn't a seed, expected a whole number", value))?);
+            },
+            "--platform" => profile.platform = Some(args.next().ok_or("--platform needs a value")?.parse()?),
+            "--quirk" => profile.quirks.set(&args.next().ok_or("--quirk needs a quirk name")?, true)?,
+            "--no-quirk" => profile.quirks.set(&args.next().ok_or("--no-quirk needs a quirk name")?, false)?,
+            "--vip-interpreter" => vip_interpreter = Some(PathBuf::from(args.next().ok_or("--vip-interpreter needs a file")?)),
+            "--beep-frequency" => {
+                let value = args.next().ok_or("--beep-frequency needs a value")?;
+                profile.audio.frequency = Some(value.parse().ok().filter(|&hz: &f32| hz > 0.0 && hz < 20_000.0)
+                    .ok_or_else(|| format!("'{}' isn't a valid frequency, expected Hz up to 20000", value))?);
+            },
+            "--beep-waveform" => profile.audio.waveform = Some(args.next().ok_or("--beep-waveform needs a value")?.parse()?),
+            "--beep-duty" => profile.audio.duty = Some(parse_fraction(args.next(), "--beep-duty")?),
+            "--volume" => profile.audio.volume = Some(parse_fraction(args.next(), "--volume")?),
+            "--mute" => profile.audio.muted = Some(true),
+            "--fast-forward" => {
+                let value = args.next().ok_or("--fast-forward needs a value")?;
+                profile.fast_forward = Some(value.parse().ok().filter(|rate| (1.0..=100.0).contains(rate))
+                    .ok_or_else(|| format!("'{}' isn't a valid rate, expected a number from 1 to 100", value))?);
+            },
+            "--fast-forward-sound" => profile.audio.fast_forward = Some(args.next().ok_or("--fast-forward-sound needs a value")?.parse()?),
+            "--play" => play_movie = Some(PathBuf::from(args.next().ok_or("--play needs a movie file")?)),
+            "--output" | "-o" => output = Some(PathBuf::from(args.next().ok_or_else(|| format!("{} needs a file", arg))?)),
+            "--profile" => profile_report = Some(PathBuf::from(args.next().ok_or("--profile needs a file")?)),
+            "--instructions" => {
+                let value = args.next().ok_or("--instructions needs a value")?;
+                instructions = value.parse().map_err(|_| format!("'{}' isn't an instruction count", value))?;
+            },
+            "--expect" => {
+                let value = args.next().ok_or("--expect needs a display hash")?;
+                if value.len() != 40 || !value.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("'{}' isn't a display hash, expected 40 hex digits", value));
+                }
+                expect = Some(value.to_ascii_lowercase());
+            },
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    // A screenshot at a given frame is taken headless, running at least long enough to reach it
+    let screenshot_frame = screenshot_at_frame.as_ref().map(|(frame, _)| *frame);
+    let headless_frames = match (headless || screenshot_frame.is_some(), frames, screenshot_frame) {
+        _ if subcommand == Subcommand::Test => Some(frames.unwrap_or(if play_movie.is_some() { 0 } else { DEFAULT_TEST_FRAMES })),
+        (false, Some(_), _) => return Err("--frames only applies to a headless run, with --headless".to_string()),
+        (false, None, _) => None,
+        (true, Some(frames), screenshot_frame) => Some(frames.max(screenshot_frame.unwrap_or(0))),
+        (true, None, Some(screenshot_frame)) => Some(screenshot_frame),
+        (true, None, None) if play_movie.is_some() => Some(0), // runs to the end of the movie, see main
+        (true, None, None) => return Err("--headless needs --frames to know how long to run".to_string()),
+    };
+
+    // Only playing can start from the browser, and only in a window or terminal
+    let needs_rom = subcommand != Subcommand::Run || headless_frames.is_some() || record_movie.is_some()
+        || play_movie.is_some() || record_video.is_some() || record_audio.is_some() || profile_report.is_some();
+    if rom.is_none() && needs_rom {
+        return Err("no ROM given".to_string());
+    }
+
+    let options = Options {
+        rom,
+        #[cfg(any(feature = "sdl", feature = "tui"))]
+        frontend,
+        profile,
+        colors,
+        screenshot_scale,
+        screenshot_at_frame,
+        record_video,
+        record_audio,
+        video_scale,
+        headless_frames,
+        config,
+        record_movie,
+        play_movie,
+        seed,
+        vip_interpreter,
+        output,
+        profile_report,
+    };
+    Ok(match subcommand {
+        Subcommand::Run => Command::Run(options),
+        Subcommand::Disasm => Command::Disasm(options),
+        Subcommand::Trace => Command::Trace(options, instructions),
+        Subcommand::Info => Command::Info(options),
+        Subcommand::Test => Command::Test(options, expect),
+        Subcommand::Lint => Command::Lint(options),
+        Subcommand::Cfg => Command::Cfg(options),
+    })
+}
+
+fn parse_fraction(value: Option<String>, flag: &str) -> Result<f32, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value.parse().ok().filter(|fraction| (0.0..=1.0).contains(fraction))
+        .ok_or_else(|| format!("'{}' isn't valid for {}, expected a number from 0 to 1", value, flag))
+}
+
+fn parse_count(value: Option<String>, flag: &str) -> Result<u32, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value.parse().ok().filter(|&scale| scale > 0).ok_or_else(|| format!("'{}' isn't valid for {}, expected a whole number above 0", value, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn run_takes_machine_options() {
+        match parse(&["--quirk", "sprites_wrap", "--speed", "12", "game.ch8"]) {
+            Ok(Command::Run(options)) => {
+                assert_eq!(options.profile.speed, Some(12));
+                assert_eq!(options.profile.quirks.sprites_wrap, Some(true));
+            },
+            other => panic!("expected run, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn trace_takes_an_instruction_count() {
+        assert!(matches!(parse(&["trace", "--instructions", "5", "game.ch8"]), Ok(Command::Trace(_, 5))));
+    }
+
+    #[test]
+    fn help_can_come_after_the_rom() {
+        assert!(matches!(parse(&["test", "game.ch8", "--help"]), Ok(Command::Help(TEST_HELP))));
+    }
+
+    #[test]
+    fn options_of_other_subcommands_are_rejected() {
+        assert!(parse(&["disasm", "--scale", "2", "game.ch8"]).is_err());
+        assert!(parse(&["run", "--instructions", "5", "game.ch8"]).is_err());
+        assert!(parse(&["run", "-o", "game.dot", "game.ch8"]).is_err());
+        assert!(parse(&["trace", "game.ch8", "--profile", "game.txt"]).is_err());
+    }
+
+    #[test]
+    fn only_run_opens_the_browser_without_a_rom() {
+        assert!(matches!(parse(&[]), Ok(Command::Run(Options { rom: None, .. }))));
+        assert!(parse(&["trace"]).is_err());
+    }
+
+    #[test]
+    fn cfg_writes_to_the_output_file() {
+        assert!(matches!(parse(&["cfg", "game.ch8", "-o", "game.dot"]), Ok(Command::Cfg(Options { output: Some(_), .. }))));
+    }
+
+    #[test]
+    fn test_can_write_a_profile() {
+        assert!(matches!(parse(&["test", "game.ch8", "--profile", "game.txt"]), Ok(Command::Test(Options { profile_report: Some(_), .. }, _))));
+    }
+
+    #[test]
+    fn profiling_needs_a_rom() {
+        assert!(parse(&["--profile", "game.txt"]).is_err()); // not from the browser
+    }
+
+    #[test]
+    fn headless_runs_for_the_given_frames() {
+        assert!(matches!(parse(&["run", "--headless", "--frames", "60", "game.ch8"]), Ok(Command::Run(Options { headless_frames: Some(60), .. }))));
+        assert!(parse(&["run", "--headless", "game.ch8"]).is_err());
+    }
+
+    #[test]
+    fn frames_without_headless_is_an_error() {
+        assert!(parse(&["run", "--frames", "60", "game.ch8"]).is_err());
+    }
+}
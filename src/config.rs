@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use chip_8::platform::{Platform, Quirks};
+
+use crate::beeper::{FastForwardSound, Tone, Waveform};
+use crate::palette::{Palette, Rgb};
+
+/// The config file, `chip_8/config.toml` in the user's config directory
+/// (`$XDG_CONFIG_HOME`, usually `~/.config`, on Linux).
+///
+/// ```toml
+/// rom_dir = "/home/me/chip8/roms"
+/// speed = 10
+/// palette = "amber"
+///
+/// [keymap]
+/// preset = "azerty"
+/// keys = { "5" = ["z", "up"] }
+///
+/// [audio]
+/// waveform = "triangle"
+/// volume = 0.3
+///
+/// # Overrides for one ROM, by the SHA-1 of its contents
+/// [rom."0123456789abcdef0123456789abcdef01234567"]
+/// platform = "vip"
+/// quirks = { sprites_wrap = true }
+/// keymap = { keys = { "2" = ["up"], "8" = ["down"] } }
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rom_dir: Option<PathBuf>, // where the ROM browser looks
+    #[serde(flatten)]
+    pub defaults: Profile,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub rom: BTreeMap<String, Profile>,
+}
+
+// Split by hand, since serde won't reject unknown settings in a flattened struct
+impl TryFrom<toml::Table> for Config {
+    type Error = toml::de::Error;
+
+    fn try_from(mut table: toml::Table) -> Result<Self, Self::Error> {
+        let rom_dir = table.remove("rom_dir").map(|dir| dir.try_into()).transpose()?;
+        // Hashes are looked up in lower case, as `rom_hash` writes them
+        let rom = match table.remove("rom") {
+            Some(rom) => rom.try_into::<BTreeMap<String, Profile>>()?.into_iter().map(|(hash, profile)| (hash.to_lowercase(), profile)).collect(),
+            None => BTreeMap::new(),
+        };
+        Ok(Config { rom_dir, defaults: toml::Value::Table(table).try_into()?, rom })
+    }
+}
+
+/// Settings for every ROM, or for one. Anything left out falls back to the defaults.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>, // instructions run each 60th of a second
+    #[serde(with = "by_name", skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>, // preset name
+    #[serde(with = "colors", skip_serializing_if = "Option::is_none")]
+    pub colors: Option<[Rgb; 4]>, // replace the palette's colours
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fast_forward: Option<f32>, // how many times faster fast-forward runs
+    #[serde(skip_serializing_if = "QuirksConfig::is_empty")]
+    pub quirks: QuirksConfig, // on top of the platform's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<KeymapConfig>,
+    #[serde(skip_serializing_if = "AudioConfig::is_empty")]
+    pub audio: AudioConfig,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, Vec<String>>, // CHIP-8 key in hex to the host keys bound to it
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirksConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift_uses_vy: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_store_increments_i: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump_uses_vx: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logic_resets_vf: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sprites_wrap: Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f32>,
+    #[serde(with = "by_name", skip_serializing_if = "Option::is_none")]
+    pub waveform: Option<Waveform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub muted: Option<bool>,
+    #[serde(with = "by_name", skip_serializing_if = "Option::is_none")]
+    pub fast_forward: Option<FastForwardSound>,
+}
+
+impl Config {
+    /// Reads the config file, or gives the defaults if there isn't one.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let config: Config = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        config.defaults.check().map_err(|e| format!("{}: {}", path.display(), e))?;
+        for (hash, profile) in config.rom.iter() {
+            profile.check().map_err(|e| format!("{}: in rom.\"{}\": {}", path.display(), hash, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Writes the config back, comments and all formatting aside.
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self).map_err(|e| e.to_string())?;
+        path.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(path, text))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The overrides for a ROM, if the config has any.
+    pub fn rom(&self, rom: &[u8]) -> Option<&Profile> {
+        self.rom.get(&rom_hash(rom))
+    }
+
+    /// The settings for a ROM: its own section on top of the defaults.
+    pub fn profile(&self, rom: &[u8]) -> Profile {
+        match self.rom(rom) {
+            Some(profile) => profile.or(&self.defaults),
+            None => self.defaults.clone(),
+        }
+    }
+}
+
+impl Profile {
+    /// These settings, with any left out taken from `fallback`. Keymaps are taken whole here;
+    /// `Keymap::from_config` is what merges them key by key.
+    pub fn or(&self, fallback: &Profile) -> Profile {
+        Profile {
+            speed: self.speed.or(fallback.speed),
+            platform: self.platform.or(fallback.platform),
+            palette: self.palette.clone().or_else(|| fallback.palette.clone()),
+            colors: self.colors.or(fallback.colors),
+            scale: self.scale.or(fallback.scale),
+            fast_forward: self.fast_forward.or(fallback.fast_forward),
+            quirks: self.quirks.or(&fallback.quirks),
+            keymap: self.keymap.clone().or_else(|| fallback.keymap.clone()),
+            audio: self.audio.or(&fallback.audio),
+        }
+    }
+
+    /// The settings here that differ from `before`, with the rest left out.
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    pub fn changed_since(&self, before: &Profile) -> Profile {
+        Profile {
+            speed: changed(&self.speed, &before.speed),
+            platform: changed(&self.platform, &before.platform),
+            palette: changed(&self.palette, &before.palette),
+            colors: changed(&self.colors, &before.colors),
+            scale: changed(&self.scale, &before.scale),
+            fast_forward: changed(&self.fast_forward, &before.fast_forward),
+            quirks: self.quirks.changed_since(&before.quirks),
+            keymap: changed(&self.keymap, &before.keymap),
+            audio: self.audio.changed_since(&before.audio),
+        }
+    }
+
+    /// The palette preset with any colours replaced, or the default palette.
+    pub fn palette(&self) -> Palette {
+        let mut palette = self.palette.as_deref().and_then(Palette::preset).unwrap_or_default();
+        if let Some(colors) = self.colors {
+            palette.name = "custom";
+            palette.colors = colors;
+        }
+        palette
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks.apply(self.platform.unwrap_or_default().quirks())
+    }
+
+    // Catches values that parse but make no sense.
+    fn check(&self) -> Result<(), String> {
+        if let Some(name) = &self.palette {
+            Palette::preset(name).ok_or_else(|| format!("unknown palette '{}'", name))?;
+        }
+        if self.speed == Some(0) || self.scale == Some(0) {
+            return Err("speed and scale must be at least 1".to_string());
+        }
+        if self.fast_forward.is_some_and(|rate| !(1.0..=100.0).contains(&rate)) {
+            return Err("fast_forward must be from 1 to 100".to_string());
+        }
+        let fractions = [self.audio.duty, self.audio.volume];
+        if fractions.iter().flatten().any(|fraction| !(0.0..=1.0).contains(fraction)) {
+            return Err("audio duty and volume must be from 0 to 1".to_string());
+        }
+        if self.audio.frequency.is_some_and(|hz| !(hz > 0.0 && hz < 20_000.0)) {
+            return Err("audio frequency must be in Hz up to 20000".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl QuirksConfig {
+    /// Names the quirks go by, in the config file and on the command line.
+    pub const NAMES: [&'static str; 5] = ["shift_uses_vy", "load_store_increments_i", "jump_uses_vx", "logic_resets_vf", "sprites_wrap"];
+
+    /// Turns a quirk on or off by name.
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let quirk = match name {
+            "shift_uses_vy" => &mut self.shift_uses_vy,
+            "load_store_increments_i" => &mut self.load_store_increments_i,
+            "jump_uses_vx" => &mut self.jump_uses_vx,
+            "logic_resets_vf" => &mut self.logic_resets_vf,
+            "sprites_wrap" => &mut self.sprites_wrap,
+            _ => return Err(format!("unknown quirk '{}', expected one of {}", name, QuirksConfig::NAMES.join(", "))),
+        };
+        *quirk = Some(value);
+        Ok(())
+    }
+
+    /// The quirks that differ from `base`, so a saved config still follows the platform for the rest.
+    pub fn difference(quirks: Quirks, base: Quirks) -> QuirksConfig {
+        let differs = |value: bool, base: bool| Some(value).filter(|&value| value != base);
+        QuirksConfig {
+            shift_uses_vy: differs(quirks.shift_uses_vy, base.shift_uses_vy),
+            load_store_increments_i: differs(quirks.load_store_increments_i, base.load_store_increments_i),
+            jump_uses_vx: differs(quirks.jump_uses_vx, base.jump_uses_vx),
+            logic_resets_vf: differs(quirks.logic_resets_vf, base.logic_resets_vf),
+            sprites_wrap: differs(quirks.sprites_wrap, base.sprites_wrap),
+        }
+    }
+
+    pub fn apply(&self, quirks: Quirks) -> Quirks {
+        Quirks {
+            shift_uses_vy: self.shift_uses_vy.unwrap_or(quirks.shift_uses_vy),
+            load_store_increments_i: self.load_store_increments_i.unwrap_or(quirks.load_store_increments_i),
+            jump_uses_vx: self.jump_uses_vx.unwrap_or(quirks.jump_uses_vx),
+            logic_resets_vf: self.logic_resets_vf.unwrap_or(quirks.logic_resets_vf),
+            sprites_wrap: self.sprites_wrap.unwrap_or(quirks.sprites_wrap),
+        }
+    }
+
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    fn changed_since(&self, before: &QuirksConfig) -> QuirksConfig {
+        QuirksConfig {
+            shift_uses_vy: changed(&self.shift_uses_vy, &before.shift_uses_vy),
+            load_store_increments_i: changed(&self.load_store_increments_i, &before.load_store_increments_i),
+            jump_uses_vx: changed(&self.jump_uses_vx, &before.jump_uses_vx),
+            logic_resets_vf: changed(&self.logic_resets_vf, &before.logic_resets_vf),
+            sprites_wrap: changed(&self.sprites_wrap, &before.sprites_wrap),
+        }
+    }
+
+    fn or(&self, fallback: &QuirksConfig) -> QuirksConfig {
+        QuirksConfig {
+            shift_uses_vy: self.shift_uses_vy.or(fallback.shift_uses_vy),
+            load_store_increments_i: self.load_store_increments_i.or(fallback.load_store_increments_i),
+            jump_uses_vx: self.jump_uses_vx.or(fallback.jump_uses_vx),
+            logic_resets_vf: self.logic_resets_vf.or(fallback.logic_resets_vf),
+            sprites_wrap: self.sprites_wrap.or(fallback.sprites_wrap),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == QuirksConfig::default()
+    }
+}
+
+impl AudioConfig {
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    pub fn from_tone(tone: Tone, muted: bool, fast_forward: FastForwardSound) -> AudioConfig {
+        AudioConfig {
+            frequency: Some(tone.frequency),
+            waveform: Some(tone.waveform),
+            duty: Some(tone.duty),
+            volume: Some(tone.volume),
+            muted: Some(muted),
+            fast_forward: Some(fast_forward),
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        let tone = Tone::default();
+        Tone {
+            frequency: self.frequency.unwrap_or(tone.frequency),
+            waveform: self.waveform.unwrap_or(tone.waveform),
+            duty: self.duty.unwrap_or(tone.duty),
+            volume: self.volume.unwrap_or(tone.volume),
+        }
+    }
+
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    fn changed_since(&self, before: &AudioConfig) -> AudioConfig {
+        AudioConfig {
+            frequency: changed(&self.frequency, &before.frequency),
+            waveform: changed(&self.waveform, &before.waveform),
+            duty: changed(&self.duty, &before.duty),
+            volume: changed(&self.volume, &before.volume),
+            muted: changed(&self.muted, &before.muted),
+            fast_forward: changed(&self.fast_forward, &before.fast_forward),
+        }
+    }
+
+    fn or(&self, fallback: &AudioConfig) -> AudioConfig {
+        AudioConfig {
+            frequency: self.frequency.or(fallback.frequency),
+            waveform: self.waveform.or(fallback.waveform),
+            duty: self.duty.or(fallback.duty),
+            volume: self.volume.or(fallback.volume),
+            muted: self.muted.or(fallback.muted),
+            fast_forward: self.fast_forward.or(fallback.fast_forward),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == AudioConfig::default()
+    }
+}
+
+// A setting that has changed from `before`, or nothing.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn changed<T: Clone + PartialEq>(now: &Option<T>, before: &Option<T>) -> Option<T> {
+    now.clone().filter(|_| now != before)
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("chip_8").join("config.toml"))
+}
+
+/// Save states go in `chip_8/states` in the user's data directory (`~/.local/share` on Linux).
+#[cfg(any(feature = "sdl", feature = "tui"))]
+pub fn states_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("chip_8").join("states"))
+}
+
+/// Identifies a ROM in the config file by the SHA-1 of its contents, in lower case hex.
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Settings written as their names, like `platform = "vip"`.
+mod by_name {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: Display, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.collect_str(value),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+        where T: FromStr<Err = String>, D: Deserializer<'de>
+    {
+        let name = Option::<String>::deserialize(deserializer)?;
+        name.map(|name| name.parse().map_err(de::Error::custom)).transpose()
+    }
+}
+
+// Four colours written as `RRGGBB` hex.
+mod colors {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::palette::Rgb;
+
+    pub fn serialize<S: Serializer>(colors: &Option<[Rgb; 4]>, serializer: S) -> Result<S::Ok, S::Error> {
+        match colors {
+            Some(colors) => serializer.collect_seq(colors.iter().map(Rgb::to_string)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[Rgb; 4]>, D::Error> {
+        let colors = match Option::<Vec<String>>::deserialize(deserializer)? {
+            Some(colors) => colors,
+            None => return Ok(None),
+        };
+        if colors.len() != 4 {
+            return Err(de::Error::custom("expected four colours"));
+        }
+        let mut parsed = [Rgb(0, 0, 0); 4];
+        for (rgb, color) in parsed.iter_mut().zip(colors.iter()) {
+            *rgb = color.parse().map_err(de::Error::custom)?;
+        }
+        Ok(Some(parsed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rom_overrides() {
+        let config: Config = toml::from_str(r#"
+            speed = 12
+            palette = "amber"
+
+            [keymap]
+            preset = "qwertz"
+
+            [rom."da39a3ee5e6b4b0d3255bfef95601890afd80709"]
+            platform = "vip"
+            quirks = { sprites_wrap = true }
+            keymap = { keys = { "2" = ["up"] } }
+        "#).unwrap();
+
+        assert_eq!(config.defaults.keymap.as_ref().unwrap().preset.as_deref(), Some("qwertz"));
+        let rom = config.rom(&[]).unwrap().keymap.as_ref().unwrap();
+        assert_eq!(rom.keys["2"], vec!["up".to_string()]);
+
+        let profile = config.profile(&[]);
+        assert_eq!(profile.speed, Some(12));
+        assert_eq!(profile.palette().name, "amber");
+        assert_eq!(profile.quirks(), Quirks { sprites_wrap: true, ..Platform::CosmacVip.quirks() });
+        assert_eq!(config.profile(&[1]).platform, None);
+    }
+
+    #[test]
+    fn rom_hashes_match_in_any_case() {
+        let config: Config = toml::from_str(r#"
+            [rom."DA39A3EE5E6B4B0D3255BFEF95601890AFD80709"]
+            speed = 20
+        "#).unwrap();
+        assert_eq!(config.profile(&[]).speed, Some(20));
+    }
+
+    #[test]
+    fn unknown_settings_are_errors() {
+        assert!(toml::from_str::<Config>("[keymap]\nlayout = \"qwerty\"").is_err());
+        assert!(toml::from_str::<Config>("sped = 8").is_err());
+        assert!(toml::from_str::<Config>("platform = \"schip\"").is_err());
+        assert!(toml::from_str::<Config>("colors = [\"000000\"]").is_err());
+    }
+
+    #[test]
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    fn saved_config_reads_back() {
+        let mut config = Config { rom_dir: Some(PathBuf::from("roms")), ..Config::default() };
+        config.defaults.audio = AudioConfig::from_tone(Tone::default(), true, FastForwardSound::Pitch);
+        config.defaults.colors = Some([Rgb(1, 2, 3), Rgb(4, 5, 6), Rgb(7, 8, 9), Rgb(10, 11, 12)]);
+        config.rom.insert("abc".to_string(), Profile {
+            platform: Some(Platform::CosmacVip),
+            fast_forward: Some(2.5),
+            quirks: QuirksConfig { jump_uses_vx: Some(true), ..QuirksConfig::default() },
+            keymap: Some(KeymapConfig { preset: None, keys: vec![("5".to_string(), vec![])].into_iter().collect() }),
+            ..Profile::default()
+        });
+
+        let text = toml::to_string(&config).unwrap();
+        let read: Config = toml::from_str(&text).unwrap();
+        assert_eq!(read.rom_dir, config.rom_dir);
+        assert_eq!(read.defaults, config.defaults);
+        assert_eq!(read.rom, config.rom);
+    }
+}
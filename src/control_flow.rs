@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+use chip_8::chip8::PROGRAM_START;
+
+use crate::analysis::{flow, Flow, Program};
+
+/// Instructions that always run one after another, only ever entered at the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub addrs: Vec<usize>,
+    pub subroutine: bool, // called as a subroutine
+}
+
+/// Where an edge goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Target {
+    Block(usize),
+    Outside(usize), // an address that isn't in the program
+    Indirect(usize), // JP V0 from this address, which depends on a register
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    Next, // on to the next instruction, or not skipping it
+    Skip,
+    Jump,
+    Call,
+    Return,
+}
+
+/// The basic blocks reachable from 0x200 and how control goes between them.
+#[derive(Debug)]
+pub struct Graph {
+    pub blocks: BTreeMap<usize, Block>, // by the address they start at
+    pub edges: BTreeSet<(usize, Target, EdgeKind)>,
+}
+
+impl Graph {
+    pub fn new(program: &Program) -> Graph {
+        // Find everything reachable, and where blocks have to start
+        let mut reachable = BTreeSet::new();
+        let mut leaders = BTreeSet::new();
+        let mut subroutines = BTreeSet::new();
+        leaders.insert(PROGRAM_START);
+        let mut queue = vec![PROGRAM_START];
+        while let Some(addr) = queue.pop() {
+            if !program.contains(addr) || !reachable.insert(addr) {
+                continue;
+            }
+            let targets = match program.decode(addr).map(flow) {
+                Some(Flow::Next) => {
+                    queue.push(addr + 2);
+                    continue;
+                },
+                Some(Flow::Skip) => vec![addr + 2, addr + 4],
+                Some(Flow::Jump(target)) => vec![target],
+                Some(Flow::Call(target)) => {
+                    subroutines.insert(target);
+                    vec![target, addr + 2]
+                },
+                Some(Flow::Return) | Some(Flow::Indirect(_)) | None => vec![],
+            };
+            leaders.extend(targets.iter().copied());
+            queue.extend(targets);
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &start in leaders.intersection(&reachable) {
+            let mut addrs = vec![start];
+            let mut addr = start;
+            while program.decode(addr).map(flow) == Some(Flow::Next) && reachable.contains(&(addr + 2)) && !leaders.contains(&(addr + 2)) {
+                addr += 2;
+                addrs.push(addr);
+            }
+            blocks.insert(start, Block { addrs, subroutine: subroutines.contains(&start) });
+        }
+
+        let target = |addr| if program.contains(addr) { Target::Block(addr) } else { Target::Outside(addr) };
+        let mut edges = BTreeSet::new();
+        for (&start, block) in &blocks {
+            let last = *block.addrs.last().unwrap();
+            match program.decode(last).map(flow) {
+                Some(Flow::Next) => {
+                    edges.insert((start, target(last + 2), EdgeKind::Next));
+                },
+                Some(Flow::Skip) => {
+                    edges.insert((start, target(last + 2), EdgeKind::Next));
+                    edges.insert((start, target(last + 4), EdgeKind::Skip));
+                },
+                Some(Flow::Jump(addr)) => {
+                    edges.insert((start, target(addr), EdgeKind::Jump));
+                },
+                Some(Flow::Call(addr)) => {
+                    edges.insert((start, target(addr), EdgeKind::Call));
+                    // Back from wherever the subroutine returns
+                    if program.contains(addr) && program.contains(last + 2) {
+                        for ret in returns(program, addr) {
+                            let block = blocks.range(..=ret).rev().find(|(_, block)| block.addrs.contains(&ret));
+                            if let Some((&from, _)) = block {
+                                edges.insert((from, Target::Block(last + 2), EdgeKind::Return));
+                            }
+                        }
+                    }
+                },
+                Some(Flow::Indirect(_)) => {
+                    edges.insert((start, Target::Indirect(last), EdgeKind::Jump));
+                },
+                Some(Flow::Return) | None => (),
+            }
+        }
+        Graph { blocks, edges }
+    }
+
+    /// Writes the graph in Graphviz's DOT language, with each block's instructions disassembled.
+    pub fn write_dot(&self, program: &Program, name: &str, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "digraph \"{}\" {{", escape(name))?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+        for (&start, block) in &self.blocks {
+            let mut label = String::new();
+            if block.subroutine {
+                label += &format!("subroutine {:#05X}\\l", start);
+            }
+            for &addr in &block.addrs {
+                let opcode = program.opcode(addr).unwrap_or_default();
+                match program.decode(addr) {
+                    Some(instruction) => label += &format!("{:03X}  {:04X}  {}\\l", addr, opcode, instruction),
+                    None => label += &format!("{:03X}  {:04X}  ??\\l", addr, opcode),
+                }
+            }
+            writeln!(out, "    {} [label=\"{}\"];", node(Target::Block(start)), escape(&label))?;
+        }
+
+        let others: BTreeSet<Target> = self.edges.iter().map(|&(_, to, _)| to).filter(|to| !matches!(to, Target::Block(_))).collect();
+        for &other in &others {
+            let label = match other {
+                Target::Outside(addr) => format!("{:#05X}\\noutside the program", addr),
+                Target::Indirect(addr) => format!("{} from {:03X}\\nunresolved", program.decode(addr).unwrap(), addr),
+                Target::Block(_) => unreachable!(),
+            };
+            writeln!(out, "    {} [label=\"{}\", shape=plaintext];", node(other), label)?;
+        }
+
+        for &(from, to, kind) in &self.edges {
+            let attributes = match (kind, to) {
+                (EdgeKind::Jump, Target::Indirect(_)) => " [label=\"jump\", style=dashed]",
+                (EdgeKind::Next, _) => "",
+                (EdgeKind::Skip, _) => " [label=\"skip\"]",
+                (EdgeKind::Jump, _) => " [label=\"jump\"]",
+                (EdgeKind::Call, _) => " [label=\"call\", color=blue]",
+                (EdgeKind::Return, _) => " [label=\"return\", color=blue, style=dashed]",
+            };
+            writeln!(out, "    {} -> {}{};", node(Target::Block(from)), node(to), attributes)?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+// The RETs a subroutine can reach, stepping over the calls it makes.
+fn returns(program: &Program, entry: usize) -> Vec<usize> {
+    let mut returns = Vec::new();
+    let mut visited = BTreeSet::new();
+    let mut queue = vec![entry];
+    while let Some(addr) = queue.pop() {
+        if !program.contains(addr) || !visited.insert(addr) {
+            continue;
+        }
+        match program.decode(addr).map(flow) {
+            Some(Flow::Next) | Some(Flow::Call(_)) => queue.push(addr + 2),
+            Some(Flow::Skip) => queue.extend_from_slice(&[addr + 2, addr + 4]),
+            Some(Flow::Jump(target)) => queue.push(target),
+            Some(Flow::Return) => returns.push(addr),
+            Some(Flow::Indirect(_)) | None => (),
+        }
+    }
+    returns
+}
+
+fn node(target: Target) -> String {
+    match target {
+        Target::Block(addr) => format!("b{:03X}", addr),
+        Target::Outside(addr) => format!("outside{:03X}", addr),
+        Target::Indirect(addr) => format!("indirect{:03X}", addr),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_blocks_at_branches() {
+        // 200: CALL 0x208; SE V0, 0x00; JP 0x200; JP V0, 0x300
+        // 208: LD V1, 0x01; RET
+        let rom = [0x22, 0x08, 0x30, 0x00, 0x12, 0x00, 0xB3, 0x00, 0x61, 0x01, 0x00, 0xEE];
+        let program = Program::new(&rom);
+        let graph = Graph::new(&program);
+
+        let starts: Vec<_> = graph.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x208]);
+        assert_eq!(graph.blocks[&0x202].addrs, [0x202]);
+        assert_eq!(graph.blocks[&0x208].addrs, [0x208, 0x20A]);
+        assert!(graph.blocks[&0x208].subroutine);
+
+        let edges: Vec<_> = graph.edges.iter().copied().collect();
+        assert_eq!(edges, [
+            (0x200, Target::Block(0x208), EdgeKind::Call),
+            (0x202, Target::Block(0x204), EdgeKind::Next),
+            (0x202, Target::Block(0x206), EdgeKind::Skip),
+            (0x204, Target::Block(0x200), EdgeKind::Jump),
+            (0x206, Target::Indirect(0x206), EdgeKind::Jump),
+            (0x208, Target::Block(0x202), EdgeKind::Return),
+        ]);
+
+        let mut dot = Vec::new();
+        graph.write_dot(&program, "test.ch8", &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph \"test.ch8\" {\n"));
+        assert!(dot.contains("    b208 [label=\"subroutine 0x208\\l208  6101  LD V1, 0x01\\l20A  00EE  RET\\l\"];\n"));
+        assert!(dot.contains("    indirect206 [label=\"JP V0, 0x300 from 206\\nunresolved\", shape=plaintext];\n"));
+        assert!(dot.contains("    b208 -> b202 [label=\"return\", color=blue, style=dashed];\n"));
+    }
+}
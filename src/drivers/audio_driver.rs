@@ -1,58 +1,57 @@
-use sdl2;
 use sdl2::audio::{AudioDevice, AudioCallback, AudioSpecDesired};
 
+use crate::beeper::{TimedBeeper, Tone, SAMPLE_RATE};
+
 pub struct AudioDriver {
     device: AudioDevice<SoundWave>,
 }
 
 impl AudioDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    pub fn new(sdl_context: &sdl2::Sdl, tone: Tone) -> Self {
         let audio_subsystem = sdl_context.audio().unwrap();
 
         let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
+            freq: Some(SAMPLE_RATE as i32),
             channels: Some(1), // mono
             samples: None, // default sample size
         };
 
         let device = audio_subsystem
             .open_playback(None, &desired_spec, |spec| {
-                // Show obtained AudioSpec
-
                 // initialize the audio callback
                 SoundWave {
-                    phase_inc: 300.0 / spec.freq as f32,
-                    phase: 0.1,
-                    volume: 0.01,
+                    beeper: TimedBeeper::new(spec.freq as u32, tone),
                 }
             })
             .unwrap();
 
+        // The device always plays, and the beeper fades in and out rather than being cut off by pausing.
+        // The sound timer is counted down in the callback, so beeps are timed by the audio clock.
+        device.resume();
         AudioDriver { device }
     }
 
-    pub fn start_beep(&self) {
-        self.device.resume();
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.device.lock().beeper.set_timer(value);
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.device.lock().beeper.set_rate(rate);
     }
-    pub fn stop_beep(&self) {
-        self.device.pause();
+
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.device.lock().beeper.beeper().set_tone(tone);
     }
 }
 
 struct SoundWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+    beeper: TimedBeeper,
 }
 
 impl AudioCallback for SoundWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = self.volume * if self.phase < 0.5 { 1.0 } else { -1.0 };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.beeper.fill(out);
     }
-}
\ No newline at end of file
+}
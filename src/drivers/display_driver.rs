@@ -1,63 +1,289 @@
-use sdl2;
 use sdl2::pixels;
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::{FullscreenType, Window};
 
-use crate::CHIP8_WIDTH;
-use crate::CHIP8_HEIGHT;
+use chip_8::CHIP8_WIDTH;
+use chip_8::CHIP8_HEIGHT;
 
-const PIXEL_SIZE: u32 = 20;
-const SCREEN_WIDTH: u32 =(CHIP8_WIDTH as u32) * PIXEL_SIZE;
-const SCREEN_HEIGHT: u32 =(CHIP8_HEIGHT as u32) * PIXEL_SIZE;
+use crate::bitmap_font::{self, ADVANCE, GLYPH_HEIGHT};
+use crate::frontend::{browser, Overlay};
+use crate::palette::{Palette, Rgb};
+
+const LETTERBOX_COLOR: pixels::Color = pixels::Color { r: 0, g: 0, b: 0, a: 0xFF };
+const OVERLAY_TEXT_COLOR: pixels::Color = pixels::Color { r: 0xFF, g: 0xFF, b: 0xFF, a: 0xFF };
+const OVERLAY_BACKGROUND: pixels::Color = pixels::Color { r: 0, g: 0, b: 0, a: 0xA0 };
+const OVERLAY_LINES: u32 = 24; // text is sized so this many lines would fill the window's height
 
 pub struct DisplayDriver {
     canvas: Canvas<Window>,
+    palette: Palette,
+    integer_scaling: bool, // only scale by whole multiples so every pixel is the same size
+    phosphor: Option<f32>, // fraction of a pixel's old colour left after each frame, None when off
+    glow: Vec<[f32; 3]>, // colour currently shown for each pixel while the phosphor filter is on
+    overlay: Overlay, // drawn over every frame, in window pixels rather than frame pixels
 }
 
 impl DisplayDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Self {
+    pub fn new(sdl_context: &sdl2::Sdl, scale: u32, integer_scaling: bool) -> Self {
         let video_subsystem = sdl_context.video().unwrap();
         let window = video_subsystem
             .window(
                 "Chip8 Emulator",
-                SCREEN_WIDTH,
-                SCREEN_HEIGHT,
+                CHIP8_WIDTH as u32 * scale,
+                CHIP8_HEIGHT as u32 * scale,
             )
             .position_centered()
+            .resizable()
             .opengl()
             .build()
             .unwrap();
 
         let mut canvas = window.into_canvas().build().unwrap();
 
-        canvas.set_draw_color(pixels::Color::RGB(0,0,0));
+        let palette = Palette::default();
+        canvas.set_draw_color(color(palette.color(0)));
         canvas.clear();
         canvas.present();
 
-        DisplayDriver { canvas }
+        DisplayDriver { canvas, palette, integer_scaling, phosphor: None, glow: Vec::new(), overlay: Overlay::default() }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Simulates CRT phosphor persistence: pixels light up straight away but fade out over
+    /// a few frames, which hides the flicker from games erasing and redrawing sprites.
+    pub fn set_phosphor(&mut self, decay: Option<f32>) {
+        self.phosphor = decay;
+        self.glow.clear();
+    }
+
+    pub fn set_overlay(&mut self, overlay: Overlay) {
+        self.overlay = overlay;
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        let _ = window.set_fullscreen(fullscreen);
     }
 
-    pub fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) { // TODO: pass in pixel buffer to draw here
+    /// Draws a frame of any size, scaled to fit the window with its aspect ratio kept.
+    /// The layout is worked out on every draw, so window resizes and resolution changes
+    /// are picked up straight away.
+    pub fn draw<R: AsRef<[u8]>>(&mut self, display: &[R]) {
+        let frame_height = display.len() as u32;
+        let frame_width = display.first().map_or(0, |row| row.as_ref().len()) as u32;
+        let output_size = self.canvas.output_size().unwrap_or((frame_width, frame_height));
+        let viewport = Viewport::fit(output_size, (frame_width, frame_height), self.integer_scaling);
+
+        self.canvas.set_draw_color(LETTERBOX_COLOR);
+        self.canvas.clear();
+
+        let pixel_count = (frame_width * frame_height) as usize;
+        if self.phosphor.is_some() && self.glow.len() != pixel_count {
+            let background = self.palette.color(0);
+            self.glow = vec![[background.0 as f32, background.1 as f32, background.2 as f32]; pixel_count];
+        }
 
-        for row in 0..CHIP8_HEIGHT {
-            for column in 0..CHIP8_WIDTH {
-                let color = color(display[row][column]);
-                self.canvas.set_draw_color(color);
-                let y = row as u32 * PIXEL_SIZE;
-                let x = column as u32 * PIXEL_SIZE;
-                let _ = self.canvas.fill_rect(Rect::new(x as i32, y as i32, PIXEL_SIZE, PIXEL_SIZE));
+        for (row, pixels) in display.iter().enumerate() {
+            for (column, &pixel) in pixels.as_ref().iter().enumerate() {
+                let mut rgb = self.palette.color(pixel);
+                if let Some(decay) = self.phosphor {
+                    let glow = &mut self.glow[row * frame_width as usize + column];
+                    rgb = fade(glow, rgb, pixel != 0, decay);
+                }
+                self.canvas.set_draw_color(color(rgb));
+                let _ = self.canvas.fill_rect(viewport.pixel_rect(column as u32, row as u32));
             }
         }
+        self.draw_overlay(output_size);
         self.canvas.present();
     }
+
+    // Stats and the pause sign go along the top, and the notification along the bottom, wrapped
+    // to the window's width.
+    fn draw_overlay(&mut self, (width, height): (u32, u32)) {
+        let text_pixel = (height / (OVERLAY_LINES * (GLYPH_HEIGHT as u32 + 2))).max(1);
+        let line_height = (GLYPH_HEIGHT as u32 + 2) * text_pixel;
+        let columns = (width / (ADVANCE as u32 * text_pixel)).saturating_sub(1) as usize;
+
+        if let Some(stats) = self.overlay.stats.clone() {
+            self.draw_text_line(&stats, 0, 0, text_pixel);
+        }
+        if self.overlay.paused {
+            let text = "PAUSED";
+            let x = width as i32 - ((text.len() * ADVANCE + 1) as u32 * text_pixel) as i32;
+            self.draw_text_line(text, x, 0, text_pixel);
+        }
+        if let Some(message) = self.overlay.message.clone() {
+            let lines = browser::wrap(&message, columns);
+            let top = height as i32 - (lines.len() as u32 * line_height) as i32;
+            for (i, line) in lines.iter().enumerate() {
+                self.draw_text_line(line, 0, top + (i as u32 * line_height) as i32, text_pixel);
+            }
+        }
+    }
+
+    // Draws one line of text on a translucent box, with its top left corner at (`x`, `y`) in window pixels.
+    fn draw_text_line(&mut self, text: &str, x: i32, y: i32, text_pixel: u32) {
+        let mut mask = vec![vec![0u8; text.chars().count() * ADVANCE]; GLYPH_HEIGHT];
+        bitmap_font::draw_text(&mut mask, 0, 0, text, 1);
+
+        let box_width = (mask[0].len() as u32 + 1) * text_pixel;
+        let box_height = (GLYPH_HEIGHT as u32 + 2) * text_pixel;
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.canvas.set_draw_color(OVERLAY_BACKGROUND);
+        let _ = self.canvas.fill_rect(Rect::new(x, y, box_width, box_height));
+        self.canvas.set_blend_mode(BlendMode::None);
+
+        self.canvas.set_draw_color(OVERLAY_TEXT_COLOR);
+        let pixel = text_pixel as i32;
+        for (row, pixels) in mask.iter().enumerate() {
+            for (column, &lit) in pixels.iter().enumerate() {
+                if lit != 0 {
+                    let left = x + (column as i32 + 1) * pixel;
+                    let top = y + (row as i32 + 1) * pixel;
+                    let _ = self.canvas.fill_rect(Rect::new(left, top, text_pixel, text_pixel));
+                }
+            }
+        }
+    }
+}
+
+/// Where the frame sits in the window: offset from the top left and size of one pixel.
+struct Viewport {
+    x: f32,
+    y: f32,
+    pixel_size: f32,
 }
 
-fn color(pixel: u8) -> pixels::Color {
-    if pixel > 0 {
-        pixels::Color::RGB(255,255,255)
-    } else {
-        pixels::Color::RGB(0,0,0)
+impl Viewport {
+    fn fit((output_width, output_height): (u32, u32), (frame_width, frame_height): (u32, u32), integer_scaling: bool) -> Self {
+        let frame_width = frame_width.max(1) as f32;
+        let frame_height = frame_height.max(1) as f32;
+        let mut pixel_size = (output_width as f32 / frame_width).min(output_height as f32 / frame_height);
+        if integer_scaling {
+            pixel_size = pixel_size.floor().max(1.0);
+        }
+
+        Viewport {
+            x: ((output_width as f32 - frame_width * pixel_size) / 2.0).floor(),
+            y: ((output_height as f32 - frame_height * pixel_size) / 2.0).floor(),
+            pixel_size,
+        }
     }
+
+    // Edges are rounded separately so neighbouring pixels meet without gaps at fractional scales
+    fn pixel_rect(&self, column: u32, row: u32) -> Rect {
+        let left = (self.x + column as f32 * self.pixel_size).round() as i32;
+        let top = (self.y + row as f32 * self.pixel_size).round() as i32;
+        let right = (self.x + (column + 1) as f32 * self.pixel_size).round() as i32;
+        let bottom = (self.y + (row + 1) as f32 * self.pixel_size).round() as i32;
+        Rect::new(left, top, (right - left).max(1) as u32, (bottom - top).max(1) as u32)
+    }
+}
+
+// Lit pixels take their colour at once, unlit ones move part of the way from the colour shown
+// last frame towards the background.
+fn fade(glow: &mut [f32; 3], target: Rgb, lit: bool, decay: f32) -> Rgb {
+    let target = [target.0 as f32, target.1 as f32, target.2 as f32];
+    for (shown, target) in glow.iter_mut().zip(target.iter()) {
+        *shown = if lit { *target } else { target + (*shown - target) * decay };
+    }
+    Rgb(glow[0].round() as u8, glow[1].round() as u8, glow[2].round() as u8)
 }
 
+fn color(rgb: Rgb) -> pixels::Color {
+    pixels::Color::RGB(rgb.0, rgb.1, rgb.2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(output: (u32, u32), integer_scaling: bool) -> Viewport {
+        Viewport::fit(output, (CHIP8_WIDTH as u32, CHIP8_HEIGHT as u32), integer_scaling)
+    }
+
+    #[test]
+    fn viewport_fills_an_output_of_the_same_shape() {
+        let viewport = viewport((640, 320), false);
+        assert_eq!((viewport.x, viewport.y, viewport.pixel_size), (0.0, 0.0, 10.0));
+        assert_eq!(viewport.pixel_rect(1, 2), Rect::new(10, 20, 10, 10));
+    }
+
+    #[test]
+    fn viewport_is_pillarboxed_in_a_wider_output() {
+        let viewport = viewport((1000, 320), false);
+        assert_eq!((viewport.x, viewport.y, viewport.pixel_size), (180.0, 0.0, 10.0));
+    }
+
+    #[test]
+    fn viewport_is_letterboxed_in_a_taller_output() {
+        let viewport = viewport((640, 500), false);
+        assert_eq!((viewport.x, viewport.y, viewport.pixel_size), (0.0, 90.0, 10.0));
+    }
+
+    #[test]
+    fn integer_scaling_centres_the_frame_in_the_space_left() {
+        let whole = viewport((700, 400), true);
+        assert_eq!((whole.x, whole.y, whole.pixel_size), (30.0, 40.0, 10.0));
+
+        // Without it pixels are 10 or 11 wide, with no gaps between them
+        let fractional = viewport((700, 400), false);
+        let (first, second) = (fractional.pixel_rect(0, 0), fractional.pixel_rect(1, 0));
+        assert_eq!(first.x() + first.width() as i32, second.x());
+        assert!((10..=11).contains(&first.width()));
+    }
+
+    #[test]
+    fn pixels_stay_visible_in_an_output_smaller_than_the_frame() {
+        assert_eq!(viewport((32, 16), true).pixel_size, 1.0);
+        let viewport = viewport((32, 16), false);
+        assert_eq!(viewport.pixel_size, 0.5);
+        assert_eq!(viewport.pixel_rect(3, 3).width(), 1);
+        assert_eq!(viewport.pixel_rect(3, 3).height(), 1);
+    }
+
+    #[test]
+    fn lit_pixels_snap_to_their_colour() {
+        let mut glow = [0.0; 3];
+        assert_eq!(fade(&mut glow, Rgb(200, 100, 50), true, 0.9), Rgb(200, 100, 50));
+        assert_eq!(glow, [200.0, 100.0, 50.0]);
+    }
+
+    #[test]
+    fn unlit_pixels_decay_towards_the_background() {
+        let mut glow = [200.0; 3];
+        assert_eq!(fade(&mut glow, Rgb(0, 0, 0), false, 0.5), Rgb(100, 100, 100));
+        assert_eq!(fade(&mut glow, Rgb(0, 0, 0), false, 0.5), Rgb(50, 50, 50));
+    }
+
+    #[test]
+    fn no_decay_turns_pixels_off_at_once() {
+        let mut glow = [200.0; 3];
+        assert_eq!(fade(&mut glow, Rgb(10, 20, 30), false, 0.0), Rgb(10, 20, 30));
+    }
+
+    #[test]
+    fn glow_stays_between_its_colour_and_the_background() {
+        // Fading down to black and up to a light background, as the LCD palette does
+        for &(start, background) in [(255.0, Rgb(0, 0, 0)), (0.0, Rgb(255, 255, 255))].iter() {
+            let mut glow = [start; 3];
+            let mut shown = start;
+            for _ in 0..200 {
+                let Rgb(red, _, _) = fade(&mut glow, background, false, 0.9);
+                let red = f32::from(red);
+                assert!((red - f32::from(background.0)).abs() <= (shown - f32::from(background.0)).abs());
+                shown = red;
+            }
+            assert_eq!(shown, f32::from(background.0));
+        }
+    }
+}
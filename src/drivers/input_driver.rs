@@ -1,6 +1,8 @@
-use sdl2;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+
+use crate::frontend::Hotkey;
+use crate::keymap::char_key_name;
 
 
 pub struct InputDriver {
@@ -14,44 +16,105 @@ impl InputDriver {
         }
     }
 
-    pub fn process_inputs(&mut self) -> Result<[bool; 16], ()> {
+    pub fn process_inputs(&mut self, hotkeys: &mut Vec<Hotkey>) -> Result<Vec<String>, ()> {
 
         for event in self.events.poll_iter() {
-			if let Event::Quit { .. } = event {
-                return Err(());
-            };
+            match event {
+                Event::Quit { .. } => return Err(()),
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => {
+                    if let Some(hotkey) = hotkey(keycode, keymod) {
+                        hotkeys.push(hotkey);
+                    }
+                },
+                _ => {},
+            }
 		}
 
-        let pressed_keys: Vec<Keycode> = self.events.keyboard_state().pressed_scancodes().filter_map(Keycode::from_scancode).collect();
-
-        let mut keys = [false; 16];
-
-        for key in pressed_keys {
-            let index = match key {
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-                Keycode::Num4 => Some(0xC),
-                Keycode::Q => Some(0x4),
-                Keycode::W => Some(0x5),
-                Keycode::E => Some(0x6),
-                Keycode::R => Some(0xD),
-                Keycode::A => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-                Keycode::F => Some(0xE),
-                Keycode::Z => Some(0xA),
-                Keycode::X => Some(0x0),
-                Keycode::C => Some(0xB),
-                Keycode::V => Some(0xF),
-                _ => None,
-            };
-
-            if let Some(i)  = index {
-                keys[i] = true;
-            }
-        }
+        let held_keys = self.events.keyboard_state().pressed_scancodes()
+            .filter_map(Keycode::from_scancode)
+            .filter_map(host_key_name)
+            .collect();
+
+        Ok(held_keys)
+    }
+}
 
-        Ok(keys)
+fn hotkey(keycode: Keycode, keymod: Mod) -> Option<Hotkey> {
+    let alt = keymod.intersects(Mod::LALTMOD | Mod::RALTMOD);
+    match keycode {
+        Keycode::F1 => Some(Hotkey::OpenBrowser),
+        Keycode::F2 => Some(Hotkey::CyclePalette),
+        Keycode::F3 => Some(Hotkey::TogglePhosphor),
+        Keycode::F4 => Some(Hotkey::CyclePhosphorDecay),
+        Keycode::F5 => Some(Hotkey::SaveState),
+        Keycode::F6 => Some(Hotkey::NextStateSlot),
+        Keycode::F7 => Some(Hotkey::LoadState),
+        Keycode::F8 => Some(Hotkey::RebindKeys),
+        Keycode::F9 => Some(Hotkey::ToggleGifRecording),
+        Keycode::F10 => Some(Hotkey::ToggleMute),
+        Keycode::F11 if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) => Some(Hotkey::SaveRomConfig),
+        Keycode::F11 => Some(Hotkey::SaveConfig),
+        Keycode::PageUp => Some(Hotkey::VolumeUp),
+        Keycode::PageDown => Some(Hotkey::VolumeDown),
+        Keycode::Return if alt => Some(Hotkey::ToggleFullscreen),
+        Keycode::F12 => Some(Hotkey::Screenshot),
+        Keycode::Backquote => Some(Hotkey::ToggleStats),
+        Keycode::Space => Some(Hotkey::TogglePause),
+        Keycode::Backslash => Some(Hotkey::AdvanceFrame),
+        Keycode::LeftBracket => Some(Hotkey::CycleSlowMotion),
+        _ => None,
     }
-}
\ No newline at end of file
+}
+
+// Names keys the way `Keymap` expects.
+fn host_key_name(keycode: Keycode) -> Option<String> {
+    let name = match keycode {
+        Keycode::Up => "up",
+        Keycode::Down => "down",
+        Keycode::Left => "left",
+        Keycode::Right => "right",
+        Keycode::Return => "enter",
+        Keycode::Tab => "tab",
+        Keycode::Escape => "escape",
+        Keycode::Backspace => "backspace",
+        Keycode::LShift => "lshift",
+        Keycode::RShift => "rshift",
+        Keycode::LCtrl => "lctrl",
+        Keycode::RCtrl => "rctrl",
+        Keycode::Kp0 => "kp0",
+        Keycode::Kp1 => "kp1",
+        Keycode::Kp2 => "kp2",
+        Keycode::Kp3 => "kp3",
+        Keycode::Kp4 => "kp4",
+        Keycode::Kp5 => "kp5",
+        Keycode::Kp6 => "kp6",
+        Keycode::Kp7 => "kp7",
+        Keycode::Kp8 => "kp8",
+        Keycode::Kp9 => "kp9",
+        Keycode::KpPlus => "kp+",
+        Keycode::KpMinus => "kp-",
+        Keycode::KpMultiply => "kp*",
+        Keycode::KpDivide => "kp/",
+        Keycode::KpPeriod => "kp.",
+        Keycode::KpEnter => "kpenter",
+        Keycode::F1 => "f1",
+        Keycode::F2 => "f2",
+        Keycode::F3 => "f3",
+        Keycode::F4 => "f4",
+        Keycode::F5 => "f5",
+        Keycode::F6 => "f6",
+        Keycode::F7 => "f7",
+        Keycode::F8 => "f8",
+        Keycode::F9 => "f9",
+        Keycode::F10 => "f10",
+        Keycode::F11 => "f11",
+        Keycode::F12 => "f12",
+        // Printable keycodes are the character the key types
+        _ => {
+            return std::char::from_u32(keycode as i32 as u32)
+                .filter(|c| c.is_ascii_graphic() || *c == ' ')
+                .map(char_key_name);
+        },
+    };
+    Some(name.to_string())
+}
@@ -0,0 +1,45 @@
+use std::error::Error;
+
+use chip_8::chip8::{Chip8, Chip8Error, NUM_KEYS};
+
+use crate::movie::Movie;
+use crate::profiler::Profiler;
+
+pub const DEFAULT_SPEED: u32 = 8; // instructions per frame: 500 / 60 is ~ 8, for roughly 500 instructions per second
+
+/// Runs `frames` frames as fast as possible, with no frontend. No keys are pressed except by a movie being played,
+/// and a desync in playback is an error.
+/// `on_frame` is called after each one with the number of frames run so far and what the program set the
+/// sound timer to, if it did.
+pub fn run_headless<F>(chip8: &mut Chip8, frames: u64, speed: u32, movie: &mut Movie, profiler: &mut Option<Profiler>, mut on_frame: F) -> Result<(), Box<dyn Error>>
+    where F: FnMut(u64, &Chip8, Option<u8>) -> Result<(), Box<dyn Error>>
+{
+    for frame in 1..=frames {
+        let keys = movie.keys([false; NUM_KEYS]);
+        chip8.set_keys(keys);
+        let sound_timer_set = emulate_frame(chip8, speed, profiler)?;
+        movie.frame(chip8, keys)?;
+        on_frame(frame, chip8, sound_timer_set)?;
+    }
+    Ok(())
+}
+
+/// Runs one frame's worth of instructions and ticks the timers, returning what the program set the sound timer to.
+/// A profiler, if there is one, counts everything that runs.
+pub fn emulate_frame(chip8: &mut Chip8, speed: u32, profiler: &mut Option<Profiler>) -> Result<Option<u8>, Chip8Error> {
+    match profiler {
+        Some(profiler) => {
+            for _ in 0..speed {
+                profiler.cycle(chip8)?;
+            }
+            profiler.end_frame();
+        },
+        None => {
+            for _ in 0..speed { // fetch decode execute loop
+                chip8.cycle()?;
+            }
+        },
+    }
+    chip8.tick_timers();
+    Ok(chip8.take_sound_timer_set())
+}
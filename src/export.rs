@@ -0,0 +1,191 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::beeper::{TimedBeeper, Tone, SAMPLE_RATE};
+use crate::capture;
+use crate::palette::Palette;
+
+const FRAMES_PER_SECOND: u32 = 60;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize; // 735, so audio and video stay in step
+
+/// Lossless export of every emulated frame as YUV4MPEG2 video and of the beeper as PCM WAV audio,
+/// for muxing afterwards with tools like ffmpeg.
+#[derive(Default)]
+pub struct Export {
+    video: Option<Y4mWriter>,
+    audio: Option<(WavWriter, TimedBeeper)>,
+}
+
+impl Export {
+    /// The audio is exported in `tone`, which muting and volume changes while running don't affect.
+    pub fn create(video: Option<&Path>, audio: Option<&Path>, video_scale: u32, tone: Tone) -> io::Result<Self> {
+        Ok(Export {
+            video: video.map(|path| Y4mWriter::create(path, video_scale)).transpose()?,
+            audio: audio.map(|path| WavWriter::create(path).map(|wav| (wav, TimedBeeper::new(SAMPLE_RATE, tone)))).transpose()?,
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.video.is_some() || self.audio.is_some()
+    }
+
+    /// Adds one emulated frame: the display as shown in `palette`, and 1/60s of beeper output
+    /// after the sound timer was set to `sound_timer_set`, if the program set it during the frame.
+    pub fn frame<R: AsRef<[u8]>>(&mut self, display: &[R], palette: &Palette, sound_timer_set: Option<u8>) -> io::Result<()> {
+        if let Some(video) = &mut self.video {
+            video.write_frame(display, palette)?;
+        }
+        if let Some((wav, beeper)) = &mut self.audio {
+            if let Some(value) = sound_timer_set {
+                beeper.set_timer(value);
+            }
+            let mut samples = [0.0; SAMPLES_PER_FRAME];
+            beeper.fill(&mut samples);
+            wav.write_samples(&samples)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(mut video) = self.video {
+            video.writer.flush()?;
+        }
+        if let Some((wav, _)) = self.audio {
+            wav.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes frames as uncompressed 4:4:4 YUV4MPEG2, which most video tools read directly.
+struct Y4mWriter {
+    writer: BufWriter<File>,
+    scale: u32,
+    size: Option<(u32, u32)>, // written into the header along with the first frame
+}
+
+impl Y4mWriter {
+    fn create(path: &Path, scale: u32) -> io::Result<Self> {
+        Ok(Y4mWriter { writer: BufWriter::new(File::create(path)?), scale, size: None })
+    }
+
+    fn write_frame<R: AsRef<[u8]>>(&mut self, display: &[R], palette: &Palette) -> io::Result<()> {
+        let image = capture::render(display, palette, self.scale);
+
+        match self.size {
+            None => {
+                writeln!(self.writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", image.width, image.height, FRAMES_PER_SECOND)?;
+                self.size = Some((image.width, image.height));
+            },
+            Some(size) if size != (image.width, image.height) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "the display size changed during video export"));
+            },
+            Some(_) => {},
+        }
+
+        let pixels = image.rgb.chunks(3).map(|rgb| rgb_to_yuv(rgb[0], rgb[1], rgb[2]));
+        let mut planes = [Vec::new(), Vec::new(), Vec::new()];
+        for yuv in pixels {
+            for (plane, &value) in planes.iter_mut().zip(yuv.iter()) {
+                plane.push(value);
+            }
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        for plane in planes.iter() {
+            self.writer.write_all(plane)?;
+        }
+        Ok(())
+    }
+}
+
+// BT.601 limited range, which is what players assume for YUV4MPEG2 without a colour space tag.
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+/// The RIFF size in the header is a u32 that also counts the 36 bytes of header after it, which
+/// limits the samples to about 13.5 hours.
+const MAX_WAV_DATA_LEN: u32 = u32::MAX - 36;
+
+/// Writes 16-bit mono PCM WAV. The sizes in the header are filled in by `finish`.
+struct WavWriter {
+    writer: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut wav = WavWriter { writer: BufWriter::new(File::create(path)?), data_len: 0 };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let channels: u16 = 1;
+        let bits_per_sample: u16 = 16;
+        let block_align = channels * bits_per_sample / 8;
+
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(36 + self.data_len).to_le_bytes())?;
+        w.write_all(b"WAVEfmt ")?;
+        w.write_all(&16u32.to_le_bytes())?; // fmt chunk size
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&channels.to_le_bytes())?;
+        w.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        w.write_all(&(SAMPLE_RATE * block_align as u32).to_le_bytes())?; // bytes per second
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&bits_per_sample.to_le_bytes())?;
+        w.write_all(b"data")?;
+        w.write_all(&self.data_len.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_len = u32::try_from(samples.len() * 2).ok()
+            .and_then(|len| self.data_len.checked_add(len))
+            .filter(|&len| len <= MAX_WAV_DATA_LEN)
+            .ok_or_else(|| io::Error::other("the audio is too long for a WAV file"))?;
+        for &sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len = data_len;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yuv_of_black_and_white() {
+        assert_eq!(rgb_to_yuv(0, 0, 0), [16, 128, 128]);
+        assert_eq!(rgb_to_yuv(255, 255, 255), [235, 128, 128]);
+    }
+
+    #[test]
+    fn wav_stops_at_the_riff_size_limit() {
+        let path = std::env::temp_dir().join("chip8_export_test.wav");
+        let mut wav = WavWriter::create(&path).unwrap();
+        wav.data_len = MAX_WAV_DATA_LEN - 2;
+        wav.write_samples(&[0.0]).unwrap();
+        assert!(wav.write_samples(&[0.0]).is_err());
+        assert_eq!(wav.data_len, MAX_WAV_DATA_LEN);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
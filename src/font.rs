@@ -1,3 +1,4 @@
+/// Sprites for the hex digits 0 to F, five bytes each, loaded at the start of memory for `LD F, Vx`.
 pub const FONT_SET: [u8; 80] = [
     
     0xF0,
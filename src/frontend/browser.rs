@@ -0,0 +1,383 @@
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+
+use chip_8::chip8::{Chip8, MAX_PROGRAM_SIZE};
+use chip_8::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+#[cfg(feature = "sdl")]
+use crate::bitmap_font::{self, ADVANCE};
+use crate::config::{self, Config, Profile};
+use crate::emulation::{emulate_frame, DEFAULT_SPEED};
+use crate::palette::Palette;
+use crate::rom_database::RomDatabase;
+use super::{Frontend, Hotkey, FRAME_TIME};
+
+// Size of the browser when drawn as pixels, four times the CHIP-8 display, and where things go on it
+#[cfg(feature = "sdl")]
+const CANVAS_WIDTH: usize = CHIP8_WIDTH * 4;
+#[cfg(feature = "sdl")]
+const CANVAS_HEIGHT: usize = CHIP8_HEIGHT * 4;
+#[cfg(feature = "sdl")]
+const LIST_WIDTH: usize = 120; // pixels, leaving the rest of the canvas for the preview
+#[cfg(feature = "sdl")]
+const LIST_TOP: usize = 10;
+#[cfg(feature = "sdl")]
+const ROW_HEIGHT: usize = 7;
+#[cfg(feature = "sdl")]
+const PREVIEW_LEFT: usize = LIST_WIDTH + 6;
+#[cfg(feature = "sdl")]
+const PREVIEW_TOP: usize = LIST_TOP;
+
+const PAGE: isize = 10; // entries Page Up, Page Down, Left and Right move by
+const REPEAT_DELAY: u32 = 24; // frames a direction key is held before it repeats
+const REPEAT_INTERVAL: u32 = 4;
+
+const EXTENSIONS: [&str; 2] = ["ch8", "c8"];
+
+static BLANK: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT] = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+
+/// A ROM in the browser.
+pub struct Entry {
+    pub path: PathBuf,
+    pub title: String,
+    pub size: usize,
+    pub profile: Profile, // what it would run with, for the preview
+    data: Vec<u8>,
+}
+
+/// What a frontend needs to draw the browser.
+pub struct View<'a> {
+    pub dir: &'a Path,
+    pub entries: &'a [Entry],
+    pub selected: usize,
+    pub preview: &'a [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT], // the selected ROM running
+}
+
+impl<'a> View<'a> {
+    /// The entries to show in a list `rows` long, keeping the selected one in view.
+    pub fn visible(&self, rows: usize) -> Range<usize> {
+        let first = self.selected.saturating_sub(rows / 2).min(self.entries.len().saturating_sub(rows));
+        first..self.entries.len().min(first + rows)
+    }
+
+    pub fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.selected)
+    }
+}
+
+/// A menu of the ROMs in a directory, with the selected one running in a preview, for picking a
+/// game without leaving the emulator.
+pub struct Browser {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+    selected: usize,
+    palette: Palette,
+    preview: Option<Chip8>, // stops at the first error, leaving its last frame up
+    preview_running: bool,
+    held: Option<Vec<String>>, // host keys held last frame, None until the first frame has been seen
+    repeat: Option<(String, u32)>, // direction key held down, and for how many frames
+}
+
+impl Browser {
+    /// Lists the ROMs in `dir`, titled from the ROM database, with the settings they would run with.
+    pub fn open(dir: &Path, config: &Config, overrides: &Profile, palette: Palette) -> io::Result<Browser> {
+        let mut database = RomDatabase::bundled();
+        database.load(&dir.join("titles.txt"))?;
+
+        let mut entries = Vec::new();
+        for file in fs::read_dir(dir)? {
+            let path = file?.path();
+            let is_rom = path.extension().and_then(|extension| extension.to_str())
+                .is_some_and(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()));
+            if !is_rom || !path.is_file() {
+                continue;
+            }
+            let data = fs::read(&path)?;
+            if data.len() > MAX_PROGRAM_SIZE {
+                continue;
+            }
+
+            let title = match database.title(&config::rom_hash(&data)) {
+                Some(title) => title.to_string(),
+                None => path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned()),
+            };
+            let profile = overrides.or(&config.profile(&data));
+            entries.push(Entry { path, title, size: data.len(), profile, data });
+        }
+        entries.sort_by_cached_key(|entry| entry.title.to_lowercase());
+
+        let mut browser = Browser {
+            dir: dir.to_path_buf(),
+            entries,
+            selected: 0,
+            palette,
+            preview: None,
+            preview_running: false,
+            held: None,
+            repeat: None,
+        };
+        browser.select(0);
+        Ok(browser)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Shows the browser until a ROM is picked, returning its path, or until the user quits.
+    pub fn choose(&mut self, frontend: &mut dyn Frontend) -> Option<PathBuf> {
+        frontend.set_palette(&self.palette);
+        frontend.set_phosphor(None);
+        frontend.set_sound_timer(0);
+        self.held = None;
+        self.repeat = None;
+        self.select(self.selected); // the preview starts again each time
+
+        let mut hotkeys = Vec::new();
+        let mut next_frame = Instant::now();
+
+        loop {
+            let held = frontend.process_inputs(&mut hotkeys).ok()?;
+            for hotkey in hotkeys.drain(..) {
+                match hotkey {
+                    Hotkey::VolumeUp => self.move_by(-PAGE),
+                    Hotkey::VolumeDown => self.move_by(PAGE),
+                    Hotkey::ToggleFullscreen => frontend.toggle_fullscreen(),
+                    _ => {},
+                }
+            }
+
+            for key in self.pressed(held) {
+                match key.as_str() {
+                    "enter" | "kpenter" if !self.is_empty() => return Some(self.entries[self.selected].path.clone()),
+                    "escape" => return None,
+                    _ => self.handle_key(&key),
+                }
+            }
+
+            self.run_preview();
+            frontend.draw_browser(&self.view());
+
+            next_frame += FRAME_TIME;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                next_frame = now;
+            }
+        }
+    }
+
+    pub fn view(&self) -> View<'_> {
+        View {
+            dir: &self.dir,
+            entries: &self.entries,
+            selected: self.selected,
+            preview: self.preview.as_ref().map_or(&BLANK, Chip8::display),
+        }
+    }
+
+    // Keys pressed since last frame, and direction keys held long enough to repeat.
+    fn pressed(&mut self, held: Vec<String>) -> Vec<String> {
+        let mut pressed: Vec<String> = match &self.held {
+            Some(previous) => held.iter().filter(|&key| !previous.contains(key)).cloned().collect(),
+            None => Vec::new(), // keys still down from the game shouldn't do anything here
+        };
+
+        self.repeat = match self.repeat.take() {
+            Some((key, frames)) if held.contains(&key) => {
+                if frames >= REPEAT_DELAY && (frames - REPEAT_DELAY).is_multiple_of(REPEAT_INTERVAL) {
+                    pressed.push(key.clone());
+                }
+                Some((key, frames + 1))
+            },
+            _ => None,
+        };
+        if let Some(key) = pressed.iter().find(|&key| direction(key).is_some()) {
+            if self.repeat.as_ref().is_none_or(|(held, _)| held != key) {
+                self.repeat = Some((key.clone(), 1));
+            }
+        }
+
+        self.held = Some(held);
+        pressed
+    }
+
+    fn handle_key(&mut self, key: &str) {
+        if let Some(step) = direction(key) {
+            self.move_by(step);
+        } else if let Some(c) = key.chars().next().filter(|c| key.len() == 1 && c.is_ascii_alphanumeric()) {
+            // Jumps to the next title starting with the key, going round from the end
+            let count = self.entries.len();
+            let next = (1..=count).map(|offset| (self.selected + offset) % count)
+                .find(|&index| self.entries[index].title.to_lowercase().starts_with(c));
+            if let Some(index) = next {
+                self.select(index);
+            }
+        }
+    }
+
+    fn move_by(&mut self, step: isize) {
+        if self.is_empty() {
+            return;
+        }
+        let last = self.entries.len() as isize - 1;
+        let index = (self.selected as isize + step).clamp(0, last) as usize;
+        if index != self.selected {
+            self.select(index);
+        }
+    }
+
+    // Selects an entry and starts it running in the preview, with no keys pressed and a fixed seed.
+    fn select(&mut self, index: usize) {
+        self.selected = index;
+        self.preview = self.entries.get(index).map(|entry| {
+            let mut chip8 = Chip8::with_seed(&entry.data, 0);
+            chip8.set_platform(entry.profile.platform.unwrap_or_default());
+            chip8.set_quirks(entry.profile.quirks());
+            chip8
+        });
+        self.preview_running = self.preview.is_some();
+    }
+
+    fn run_preview(&mut self) {
+        let speed = self.entries.get(self.selected).and_then(|entry| entry.profile.speed).unwrap_or(DEFAULT_SPEED);
+        if let (Some(chip8), true) = (&mut self.preview, self.preview_running) {
+            self.preview_running = emulate_frame(chip8, speed, &mut None).is_ok();
+        }
+    }
+}
+
+fn direction(key: &str) -> Option<isize> {
+    match key {
+        "up" | "kp8" => Some(-1),
+        "down" | "kp2" => Some(1),
+        "left" | "kp4" => Some(-PAGE),
+        "right" | "kp6" => Some(PAGE),
+        _ => None,
+    }
+}
+
+/// Draws the browser as pixels in the CHIP-8 palette's colours: the list on the left with the
+/// selected ROM highlighted, and its preview and details on the right.
+#[cfg(feature = "sdl")]
+pub fn render(view: &View) -> Vec<[u8; CANVAS_WIDTH]> {
+    let mut canvas = vec![[0; CANVAS_WIDTH]; CANVAS_HEIGHT];
+    let columns = |width: usize| width / ADVANCE;
+
+    let count = format!("{}/{}", (view.selected + 1).min(view.entries.len()), view.entries.len());
+    let header = fit(&format!("ROMS IN {}", view.dir.display()), columns(CANVAS_WIDTH) - count.len() - 2);
+    bitmap_font::draw_text(&mut canvas, 1, 1, &header, 1);
+    bitmap_font::draw_text(&mut canvas, CANVAS_WIDTH - count.len() * ADVANCE, 1, &count, 1);
+    fill(&mut canvas, 0..CANVAS_WIDTH, 7..8, 1);
+
+    if view.entries.is_empty() {
+        bitmap_font::draw_text(&mut canvas, 1, LIST_TOP, "NO ROMS FOUND", 1);
+        return canvas;
+    }
+
+    let rows = (CANVAS_HEIGHT - LIST_TOP) / ROW_HEIGHT;
+    for (row, index) in view.visible(rows).enumerate() {
+        let y = LIST_TOP + row * ROW_HEIGHT;
+        let title = fit(&view.entries[index].title, columns(LIST_WIDTH - 2));
+        if index == view.selected {
+            fill(&mut canvas, 0..LIST_WIDTH, y - 1..y + ROW_HEIGHT - 1, 1);
+            bitmap_font::draw_text(&mut canvas, 1, y, &title, 0);
+        } else {
+            bitmap_font::draw_text(&mut canvas, 1, y, &title, 1);
+        }
+    }
+
+    // The preview at twice the size, in a frame
+    let preview_right = PREVIEW_LEFT + CHIP8_WIDTH * 2;
+    let preview_bottom = PREVIEW_TOP + CHIP8_HEIGHT * 2;
+    fill(&mut canvas, PREVIEW_LEFT - 2..preview_right + 2, PREVIEW_TOP - 2..preview_bottom + 2, 1);
+    fill(&mut canvas, PREVIEW_LEFT - 1..preview_right + 1, PREVIEW_TOP - 1..preview_bottom + 1, 0);
+    for (y, line) in view.preview.iter().enumerate() {
+        for (x, &pixel) in line.iter().enumerate() {
+            let left = PREVIEW_LEFT + x * 2;
+            let top = PREVIEW_TOP + y * 2;
+            fill(&mut canvas, left..left + 2, top..top + 2, pixel);
+        }
+    }
+
+    if let Some(entry) = view.selected() {
+        let details_width = columns(CANVAS_WIDTH - PREVIEW_LEFT);
+        let file_name = entry.path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let mut lines = wrap(&entry.title, details_width);
+        lines.truncate(2);
+        lines.push(String::new());
+        lines.push(fit(&file_name, details_width));
+        lines.push(format!("{} BYTES, {}", entry.size, entry.profile.platform.unwrap_or_default()));
+        lines.push("ENTER TO PLAY".to_string());
+        for (i, line) in lines.iter().enumerate() {
+            bitmap_font::draw_text(&mut canvas, PREVIEW_LEFT - 2, preview_bottom + 5 + i * ROW_HEIGHT, line, 1);
+        }
+    }
+    canvas
+}
+
+#[cfg(feature = "sdl")]
+fn fill(canvas: &mut [[u8; CANVAS_WIDTH]], columns: Range<usize>, rows: Range<usize>, color: u8) {
+    for line in &mut canvas[rows] {
+        for pixel in &mut line[columns.clone()] {
+            *pixel = color;
+        }
+    }
+}
+
+/// Cuts text down to `columns` characters, ending in `..` if anything was left off.
+pub fn fit(text: &str, columns: usize) -> String {
+    if text.chars().count() <= columns {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(columns.saturating_sub(2)).collect();
+    cut.push_str("..");
+    cut
+}
+
+/// Breaks text into lines of at most `columns` characters, between words where it can.
+pub fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= columns => {
+                line.push(' ');
+                line.push_str(word);
+            },
+            _ => lines.push(fit(word, columns)),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_stays_in_view() {
+        let entries: Vec<Entry> = (0..30).map(|i| Entry {
+            path: PathBuf::from(format!("{}.ch8", i)),
+            title: i.to_string(),
+            size: 0,
+            profile: Profile::default(),
+            data: Vec::new(),
+        }).collect();
+        let view = |selected| View { dir: Path::new("."), entries: &entries, selected, preview: &BLANK };
+
+        assert_eq!(view(0).visible(10), 0..10);
+        assert_eq!(view(15).visible(10), 10..20);
+        assert_eq!(view(29).visible(10), 20..30);
+    }
+
+    #[test]
+    fn titles_wrap_and_fit_their_width() {
+        assert_eq!(wrap("Space Invaders (David Winter)", 16), vec!["Space Invaders", "(David Winter)"]);
+        assert_eq!(fit("Space Invaders", 8), "Space ..");
+    }
+}
@@ -0,0 +1,556 @@
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chip_8::chip8::{Chip8, Chip8Error, NUM_KEYS};
+use chip_8::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+use crate::beeper::{FastForwardSound, Tone};
+use crate::capture;
+use crate::config::{AudioConfig, Config, Profile, QuirksConfig};
+use crate::emulation::emulate_frame;
+use crate::export::Export;
+use crate::gif_recorder::GifRecorder;
+use crate::keymap::{self, Keymap, FAST_FORWARD_KEY, KEYPAD_ORDER};
+use crate::movie::Movie;
+use crate::palette::Palette;
+use crate::profiler::Profiler;
+
+pub mod browser;
+mod osd;
+#[cfg(feature = "sdl")]
+mod sdl;
+#[cfg(feature = "tui")]
+mod tui;
+
+pub use self::browser::Browser;
+pub use self::osd::Overlay;
+#[cfg(feature = "sdl")]
+pub use self::sdl::SdlFrontend;
+#[cfg(feature = "tui")]
+pub use self::tui::TuiFrontend;
+
+const FRAME_TIME: Duration = Duration::from_micros(16_667); // timers and the display run at 60Hz
+pub const DEFAULT_FAST_FORWARD: f32 = 4.0;
+
+const SLOW_MOTION_RATES: [f32; 3] = [1.0, 0.5, 0.25]; // the slow motion hotkey steps through these
+
+const STATE_SLOTS: u32 = 10;
+const VOLUME_STEP: f32 = 0.1;
+
+/// How much of the previous frame is left on screen for each phosphor strength the hotkey steps through.
+pub const PHOSPHOR_DECAYS: [f32; 3] = [0.5, 0.7, 0.85];
+
+/// Emulator controls bound to host keys outside the keypad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    OpenBrowser, // F1
+    CyclePalette, // F2
+    TogglePhosphor, // F3
+    CyclePhosphorDecay, // F4
+    SaveState, // F5
+    NextStateSlot, // F6
+    LoadState, // F7
+    RebindKeys, // F8
+    ToggleGifRecording, // F9
+    ToggleMute, // F10
+    VolumeUp, // Page Up
+    VolumeDown, // Page Down
+    SaveConfig, // F11
+    SaveRomConfig, // Shift+F11
+    ToggleFullscreen, // Alt+Enter
+    Screenshot, // F12
+    ToggleStats, // `
+    TogglePause, // Space
+    AdvanceFrame, // \
+    CycleSlowMotion, // [
+}
+
+/// Something that can show the CHIP-8 display, play its beeper and read its keypad.
+pub trait Frontend {
+    /// Returns the names of the host keys held down (see `Keymap`), or `Err` if the user asked to quit.
+    /// Hotkeys pressed since the last call are pushed onto `hotkeys`.
+    fn process_inputs(&mut self, hotkeys: &mut Vec<Hotkey>) -> Result<Vec<String>, ()>;
+    fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]);
+    fn draw_browser(&mut self, view: &browser::View);
+    /// Sets the text drawn over the display from the next `draw` on.
+    fn set_overlay(&mut self, overlay: &Overlay);
+    /// Called when the program sets the sound timer. Frontends with sound count it down themselves
+    /// at 60Hz, on the audio clock.
+    fn set_sound_timer(&mut self, _value: u8) {} // not every frontend has sound
+    fn set_tone(&mut self, _tone: &Tone) {}
+    fn set_palette(&mut self, palette: &Palette);
+    fn toggle_fullscreen(&mut self) {} // not every frontend has a window
+    /// Turns the phosphor persistence filter on with the given decay, or off with `None`.
+    fn set_phosphor(&mut self, _decay: Option<f32>) {}
+    /// Counts the sound timer down `rate` times faster than 60Hz, while the game runs faster or slower.
+    fn set_sound_rate(&mut self, _rate: f32) {}
+}
+
+/// Settings that can be changed while the emulator runs.
+pub struct Settings {
+    pub keymap: Keymap,
+    pub speed: u32, // instructions per frame
+    pub palettes: Vec<Palette>, // the cycle hotkey steps through these, starting with the first
+    pub phosphor: bool,
+    pub phosphor_decay: f32,
+    pub screenshot_scale: u32,
+    pub gif_scale: u32,
+    pub tone: Tone,
+    pub muted: bool,
+    pub fast_forward: f32, // how many times faster than normal fast-forward runs
+    pub fast_forward_sound: FastForwardSound,
+    pub scale: u32, // only kept to save to the config
+    pub stats: bool, // show the frame rate and speed on screen
+    pub states_dir: Option<PathBuf>, // where save states go, if there is anywhere
+    pub config_path: Option<PathBuf>, // where settings are saved to
+    pub rom_hash: String, // names the ROM's save states and config section
+}
+
+/// Why a game stopped without an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Quit, // the user closed the emulator
+    Browse, // the user asked for the ROM browser, to pick another game
+}
+
+/// State for one run of the emulator in a frontend.
+struct Session<'a> {
+    chip8: &'a mut Chip8,
+    frontend: &'a mut dyn Frontend,
+    settings: &'a mut Settings,
+    export: &'a mut Export,
+    movie: &'a mut Movie,
+    profiler: &'a mut Option<Profiler>,
+    palette_index: usize,
+    gif: Option<GifRecorder>,
+    held: Vec<String>, // host keys held down last frame
+    rebinding: Option<usize>, // position in KEYPAD_ORDER waiting for a host key
+    state_slot: u32,
+    open_browser: bool,
+    osd: osd::Osd,
+    paused: bool,
+    advance_frame: bool, // run one frame while paused
+    slow_motion: usize, // index into SLOW_MOTION_RATES
+    fast_forwarding: bool,
+    frames_due: f32, // emulated frames owed, as slow motion runs less than one a frame
+    started: Profile, // the settings as they were at the start, to tell what's been changed since
+}
+
+pub fn run(chip8: &mut Chip8, frontend: &mut dyn Frontend, settings: &mut Settings, export: &mut Export, movie: &mut Movie, profiler: &mut Option<Profiler>) -> Result<Exit, Chip8Error> {
+    let osd = osd::Osd::new(settings.stats);
+    let started = current_profile(chip8, settings, &settings.palettes[0]);
+    let mut session = Session {
+        chip8,
+        frontend,
+        settings,
+        export,
+        movie,
+        profiler,
+        palette_index: 0,
+        gif: None,
+        held: Vec::new(),
+        rebinding: None,
+        state_slot: 0,
+        open_browser: false,
+        osd,
+        paused: false,
+        advance_frame: false,
+        slow_motion: 0,
+        fast_forwarding: false,
+        frames_due: 0.0,
+        started,
+    };
+    let result = session.run();
+    session.stop_gif_recording();
+    session.frontend.set_overlay(&Overlay::default());
+    result
+}
+
+// Every setting as it is now, as it would be saved to the config.
+fn current_profile(chip8: &Chip8, settings: &Settings, palette: &Palette) -> Profile {
+    let platform = chip8.platform();
+    Profile {
+        speed: Some(settings.speed),
+        platform: Some(platform),
+        palette: Some(palette).filter(|palette| Palette::preset(palette.name).is_some()).map(|palette| palette.name.to_string()),
+        colors: Some(palette.colors).filter(|_| Palette::preset(palette.name).is_none()),
+        scale: Some(settings.scale),
+        fast_forward: Some(settings.fast_forward),
+        quirks: QuirksConfig::difference(chip8.quirks(), platform.quirks()),
+        keymap: Some(settings.keymap.to_config()),
+        audio: AudioConfig::from_tone(settings.tone, settings.muted, settings.fast_forward_sound),
+    }
+}
+
+// The defaults with only what was changed while playing saved over them. What the game started with
+// includes its own section of the config and the command line, which mustn't spread to every ROM.
+fn updated_defaults(defaults: &Profile, started: &Profile, now: &Profile) -> Profile {
+    let mut updated = now.changed_since(started).or(defaults);
+    if (&now.palette, now.colors) != (&started.palette, started.colors) {
+        // A preset and custom colours replace each other, rather than the colours going over the preset
+        updated.palette = now.palette.clone();
+        updated.colors = now.colors;
+    }
+    updated
+}
+
+impl<'a> Session<'a> {
+    fn run(&mut self) -> Result<Exit, Chip8Error> {
+        self.frontend.set_palette(&self.settings.palettes[self.palette_index]);
+        self.frontend.set_phosphor(self.phosphor());
+        self.frontend.set_tone(&self.tone());
+        self.frontend.draw(self.chip8.display());
+
+        let mut hotkeys = Vec::new();
+        let mut next_frame = Instant::now();
+
+        loop {
+            let held = match self.frontend.process_inputs(&mut hotkeys) {
+                Ok(held) => held,
+                Err(()) => return Ok(Exit::Quit),
+            };
+
+            for hotkey in hotkeys.drain(..) {
+                self.handle_hotkey(hotkey);
+            }
+            if self.open_browser {
+                self.frontend.set_sound_timer(0);
+                return Ok(Exit::Browse);
+            }
+
+            if let Some(position) = self.rebinding {
+                // The game waits while the keypad is being rebound
+                self.rebind(position, &held);
+                self.frontend.set_overlay(&self.osd.overlay(true));
+                match self.rebinding {
+                    Some(position) => self.frontend.draw(&keymap::rebind_screen(position)),
+                    None => self.sync_sound_timer(), // back to the game
+                }
+            } else {
+                self.set_fast_forwarding(held.iter().any(|host_key| host_key == FAST_FORWARD_KEY));
+                // The host still runs at 60Hz, but runs however many emulated frames are due each time round
+                let frames = if self.paused {
+                    std::mem::take(&mut self.advance_frame) as u32
+                } else {
+                    self.frames_due += self.rate();
+                    let frames = self.frames_due.floor();
+                    self.frames_due -= frames;
+                    frames as u32
+                };
+                for _ in 0..frames {
+                    self.emulate_frame(&held)?;
+                }
+                self.frontend.set_overlay(&self.osd.overlay(self.paused));
+                self.frontend.draw(self.chip8.display());
+            }
+            self.held = held;
+
+            // Sleep until the next frame is due, rather than a fixed time, so slow frames don't add up
+            next_frame += FRAME_TIME;
+            let now = Instant::now();
+            if next_frame > now {
+                thread::sleep(next_frame - now);
+            } else {
+                next_frame = now;
+            }
+        }
+    }
+
+    fn emulate_frame(&mut self, held: &[String]) -> Result<(), Chip8Error> {
+        let keys = self.movie.keys(self.settings.keymap.keys(held));
+        self.chip8.set_keys(keys);
+        let sound_timer_set = emulate_frame(self.chip8, self.settings.speed, self.profiler)?;
+        if let Some(value) = sound_timer_set.filter(|_| !self.paused) { // frames advanced while paused are silent
+            self.frontend.set_sound_timer(value);
+        }
+        self.osd.count_frame(self.settings.speed);
+        self.record_frame(sound_timer_set);
+        self.movie_frame(keys);
+        Ok(())
+    }
+
+    fn handle_hotkey(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::OpenBrowser => self.open_browser = true,
+            Hotkey::CyclePalette => {
+                self.palette_index = (self.palette_index + 1) % self.settings.palettes.len();
+                self.frontend.set_palette(&self.settings.palettes[self.palette_index]);
+            },
+            Hotkey::TogglePhosphor => {
+                self.settings.phosphor = !self.settings.phosphor;
+                self.frontend.set_phosphor(self.phosphor());
+            },
+            Hotkey::CyclePhosphorDecay => {
+                self.settings.phosphor_decay = next_phosphor_decay(self.settings.phosphor_decay);
+                self.settings.phosphor = true;
+                self.frontend.set_phosphor(self.phosphor());
+            },
+            Hotkey::SaveState => self.save_state(),
+            Hotkey::NextStateSlot => {
+                self.state_slot = (self.state_slot + 1) % STATE_SLOTS;
+                self.osd.notify(format!("State slot {}", self.state_slot));
+            },
+            Hotkey::LoadState => self.load_state(),
+            Hotkey::RebindKeys => {
+                if self.rebinding.is_some() {
+                    self.rebinding = None;
+                    self.sync_sound_timer();
+                    self.osd.notify("Rebinding cancelled".to_string());
+                } else {
+                    self.rebinding = Some(0);
+                    self.frontend.set_sound_timer(0);
+                }
+            },
+            Hotkey::ToggleGifRecording => {
+                if self.gif.is_some() {
+                    self.stop_gif_recording();
+                } else {
+                    self.start_gif_recording();
+                }
+            },
+            Hotkey::ToggleMute => {
+                self.settings.muted = !self.settings.muted;
+                self.frontend.set_tone(&self.tone());
+                self.osd.notify(if self.settings.muted { "Muted" } else { "Unmuted" }.to_string());
+            },
+            Hotkey::VolumeUp | Hotkey::VolumeDown => {
+                let step = if hotkey == Hotkey::VolumeUp { VOLUME_STEP } else { -VOLUME_STEP };
+                let volume = &mut self.settings.tone.volume;
+                *volume = ((*volume + step) / VOLUME_STEP).round() * VOLUME_STEP; // stay on whole steps
+                *volume = volume.clamp(0.0, 1.0);
+                self.settings.muted = false;
+                self.frontend.set_tone(&self.tone());
+                self.osd.notify(format!("Volume {}%", (self.settings.tone.volume * 100.0).round()));
+            },
+            Hotkey::SaveConfig => self.save_config(false),
+            Hotkey::SaveRomConfig => self.save_config(true),
+            Hotkey::ToggleFullscreen => self.frontend.toggle_fullscreen(),
+            Hotkey::Screenshot => {
+                let image = capture::render(self.chip8.display(), self.palette(), self.settings.screenshot_scale);
+                let path = capture::timestamped_path("chip8", "png");
+                match capture::save_png(&path, &image) {
+                    Ok(()) => self.osd.notify(format!("Saved screenshot to {}", path.display())),
+                    Err(e) => self.osd.notify(format!("Couldn't save screenshot to {}: {}", path.display(), e)),
+                }
+            },
+            Hotkey::ToggleStats => self.osd.toggle_stats(),
+            Hotkey::TogglePause => self.set_paused(!self.paused),
+            Hotkey::AdvanceFrame => {
+                self.set_paused(true);
+                self.advance_frame = true;
+            },
+            Hotkey::CycleSlowMotion => {
+                self.slow_motion = (self.slow_motion + 1) % SLOW_MOTION_RATES.len();
+                self.frames_due = 0.0;
+                self.frontend.set_sound_rate(self.rate());
+                match SLOW_MOTION_RATES[self.slow_motion] {
+                    rate if rate < 1.0 => self.osd.notify(format!("Slow motion {}x", rate)),
+                    _ => self.osd.notify("Normal speed".to_string()),
+                }
+            },
+        }
+    }
+
+    // Binds the first host key pressed this frame to the keypad key being rebound, then moves on to the next.
+    fn rebind(&mut self, position: usize, held: &[String]) {
+        let key = KEYPAD_ORDER[position];
+        let (already_held, keymap) = (&self.held, &mut self.settings.keymap);
+        if held.iter().filter(|&host_key| !already_held.contains(host_key)).any(|host_key| keymap.rebind(host_key, key)) {
+            self.rebinding = Some(position + 1).filter(|&next| next < KEYPAD_ORDER.len());
+        }
+    }
+
+    fn save_state(&mut self) {
+        let path = match self.state_path() {
+            Some(path) => path,
+            None => return self.osd.notify("Nowhere to save states".to_string()),
+        };
+        let result = path.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, self.chip8.save_state()));
+        match result {
+            Ok(()) => self.osd.notify(format!("State {} saved", self.state_slot)),
+            Err(e) => self.osd.notify(format!("Couldn't save state to {}: {}", path.display(), e)),
+        }
+    }
+
+    fn load_state(&mut self) {
+        let path = match self.state_path() {
+            Some(path) => path,
+            None => return self.osd.notify("Nowhere to load states from".to_string()),
+        };
+        if self.movie.is_active() {
+            // The movie only has the keys, so it would carry on from the wrong place
+            return self.osd.notify("States can't be loaded during a movie".to_string());
+        }
+        let result = fs::read(&path).map_err(|e| e.to_string())
+            .and_then(|state| self.chip8.load_state(&state).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => {
+                self.osd.notify(format!("State {} loaded", self.state_slot));
+                self.sync_sound_timer();
+                self.frontend.draw(self.chip8.display());
+            },
+            Err(e) => self.osd.notify(format!("Couldn't load state from {}: {}", path.display(), e)),
+        }
+    }
+
+    fn state_path(&self) -> Option<PathBuf> {
+        let dir = self.settings.states_dir.as_ref()?;
+        Some(dir.join(format!("{}-{}.state", self.settings.rom_hash, self.state_slot)))
+    }
+
+    // Writes the settings in use to the config file, as the defaults or for this ROM only.
+    fn save_config(&mut self, for_rom: bool) {
+        let path = match &self.settings.config_path {
+            Some(path) => path.clone(),
+            None => return self.osd.notify("Nowhere to save settings".to_string()),
+        };
+        let profile = current_profile(self.chip8, self.settings, self.palette());
+
+        // Read the file again so nothing written since this started is lost
+        let result = Config::load(&path).and_then(|mut config| {
+            if for_rom {
+                config.rom.insert(self.settings.rom_hash.clone(), profile);
+            } else {
+                config.defaults = updated_defaults(&config.defaults, &self.started, &profile);
+            }
+            config.save(&path)
+        });
+        match result {
+            Ok(()) if for_rom => self.osd.notify(format!("Settings saved for this ROM to {}", path.display())),
+            Ok(()) => self.osd.notify(format!("Settings saved to {}", path.display())),
+            Err(e) => self.osd.notify(format!("Couldn't save settings: {}", e)),
+        }
+    }
+
+    fn start_gif_recording(&mut self) {
+        let path = capture::timestamped_path("chip8", "gif");
+        match GifRecorder::create(&path, self.palette(), CHIP8_WIDTH, CHIP8_HEIGHT, self.settings.gif_scale) {
+            Ok(recorder) => {
+                self.osd.notify(format!("Recording to {}", path.display()));
+                self.gif = Some(recorder);
+            },
+            Err(e) => self.osd.notify(format!("Couldn't start recording to {}: {}", path.display(), e)),
+        }
+    }
+
+    fn stop_gif_recording(&mut self) {
+        if let Some(recorder) = self.gif.take() {
+            let path = recorder.path().to_path_buf();
+            match recorder.finish() {
+                Ok(path) => self.osd.notify(format!("Saved recording to {}", path.display())),
+                Err(e) => self.osd.notify(format!("Couldn't finish recording to {}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    fn record_frame(&mut self, sound_timer_set: Option<u8>) {
+        if let Some(recorder) = &mut self.gif {
+            if let Err(e) = recorder.add_frame(self.chip8.display()) {
+                self.osd.notify(format!("Recording to {} stopped: {}", recorder.path().display(), e));
+                self.gif = None;
+            }
+        }
+        let palette = &self.settings.palettes[self.palette_index];
+        if let Err(e) = self.export.frame(self.chip8.display(), palette, sound_timer_set) {
+            self.osd.notify(format!("Export stopped: {}", e));
+            *self.export = Export::default();
+        }
+    }
+
+    fn movie_frame(&mut self, keys: [bool; NUM_KEYS]) {
+        match self.movie.frame(self.chip8, keys) {
+            Ok(true) => self.osd.notify("Movie finished, the keypad is yours".to_string()),
+            Ok(false) => {},
+            Err(e) => {
+                self.osd.notify(format!("Movie stopped: {}", e));
+                *self.movie = Movie::default();
+            },
+        }
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            self.paused = paused;
+            self.sync_sound_timer();
+        }
+    }
+
+    // The beep stops while the game waits, and carries on from where it was when it goes on.
+    fn sync_sound_timer(&mut self) {
+        let waiting = self.paused || self.rebinding.is_some();
+        self.frontend.set_sound_timer(if waiting { 0 } else { self.chip8.sound_timer() });
+    }
+
+    fn set_fast_forwarding(&mut self, fast_forwarding: bool) {
+        if fast_forwarding != self.fast_forwarding {
+            self.fast_forwarding = fast_forwarding;
+            self.frontend.set_tone(&self.tone());
+            self.frontend.set_sound_rate(self.rate());
+        }
+    }
+
+    // How many emulated frames run for each real one.
+    fn rate(&self) -> f32 {
+        if self.fast_forwarding {
+            self.settings.fast_forward
+        } else {
+            SLOW_MOTION_RATES[self.slow_motion]
+        }
+    }
+
+    fn palette(&self) -> &Palette {
+        &self.settings.palettes[self.palette_index]
+    }
+
+    fn tone(&self) -> Tone {
+        let fast_forward_sound = Some(self.settings.fast_forward_sound).filter(|_| self.fast_forwarding);
+        if self.settings.muted || fast_forward_sound == Some(FastForwardSound::Mute) {
+            Tone { volume: 0.0, ..self.settings.tone }
+        } else if fast_forward_sound == Some(FastForwardSound::Pitch) {
+            let frequency = (self.settings.tone.frequency * self.settings.fast_forward).min(20_000.0);
+            Tone { frequency, ..self.settings.tone }
+        } else {
+            self.settings.tone
+        }
+    }
+
+    fn phosphor(&self) -> Option<f32> {
+        if self.settings.phosphor {
+            Some(self.settings.phosphor_decay)
+        } else {
+            None
+        }
+    }
+}
+
+// Steps up to the next stronger preset, wrapping back round to the weakest.
+fn next_phosphor_decay(decay: f32) -> f32 {
+    PHOSPHOR_DECAYS.iter().copied().find(|&preset| preset > decay).unwrap_or(PHOSPHOR_DECAYS[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chip_8::Platform;
+
+    use crate::palette::Rgb;
+
+    #[test]
+    fn saving_defaults_keeps_the_roms_own_settings_out() {
+        let defaults = Profile { speed: Some(10), colors: Some([Rgb(1, 2, 3); 4]), ..Profile::default() };
+        let mut started = Profile { speed: Some(20), platform: Some(Platform::CosmacVip), palette: Some("amber".to_string()), ..Profile::default() };
+        started.audio.volume = Some(0.5);
+        let mut now = Profile { palette: Some("green".to_string()), ..started.clone() };
+        now.audio.volume = Some(0.8);
+
+        let updated = updated_defaults(&defaults, &started, &now);
+        assert_eq!(updated.speed, Some(10));
+        assert_eq!(updated.platform, None);
+        assert_eq!((updated.palette.as_deref(), updated.colors), (Some("green"), None));
+        assert_eq!(updated.audio.volume, Some(0.8));
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+use super::FRAME_TIME;
+
+const MESSAGE_TIME: Duration = Duration::from_secs(3); // how long a notification stays up
+const SAMPLE_TIME: Duration = Duration::from_millis(500); // how often the figures are worked out again
+
+/// Text drawn over the game by the frontend, leaving the CHIP-8 display itself alone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Overlay {
+    pub stats: Option<String>, // frame rate and speed, in the top left
+    pub paused: bool, // shown in the top right
+    pub message: Option<String>, // the latest notification, along the bottom
+}
+
+/// Keeps the figures and notifications for the on-screen display.
+pub struct Osd {
+    show_stats: bool,
+    message: Option<(String, Instant)>, // with when it goes away
+    sample_start: Instant,
+    frames: u32, // emulated since the sample started
+    instructions: u64,
+    fps: f64,
+    ips: f64,
+}
+
+impl Osd {
+    pub fn new(show_stats: bool) -> Self {
+        Osd {
+            show_stats,
+            message: None,
+            sample_start: Instant::now(),
+            frames: 0,
+            instructions: 0,
+            fps: 0.0,
+            ips: 0.0,
+        }
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats;
+    }
+
+    /// Shows a message for a few seconds. It goes to stderr too, so there is a record of it.
+    pub fn notify(&mut self, message: String) {
+        eprintln!("{}", message);
+        self.message = Some((message, Instant::now() + MESSAGE_TIME));
+    }
+
+    /// Counts an emulated frame that ran `instructions` instructions.
+    pub fn count_frame(&mut self, instructions: u32) {
+        self.frames += 1;
+        self.instructions += u64::from(instructions);
+
+        let elapsed = self.sample_start.elapsed();
+        if elapsed >= SAMPLE_TIME {
+            self.fps = f64::from(self.frames) / elapsed.as_secs_f64();
+            self.ips = self.instructions as f64 / elapsed.as_secs_f64();
+            self.frames = 0;
+            self.instructions = 0;
+            self.sample_start = Instant::now();
+        }
+    }
+
+    pub fn overlay(&mut self, paused: bool) -> Overlay {
+        if self.message.as_ref().is_some_and(|(_, until)| Instant::now() >= *until) {
+            self.message = None;
+        }
+        Overlay {
+            stats: Some(stats_text(self.fps, self.ips)).filter(|_| self.show_stats),
+            paused,
+            message: self.message.as_ref().map(|(message, _)| message.clone()),
+        }
+    }
+}
+
+// The speed is how the emulated frame rate compares with the 60Hz it should run at.
+fn stats_text(fps: f64, ips: f64) -> String {
+    let speed = fps * FRAME_TIME.as_secs_f64();
+    format!("{:.0} FPS  {:.0} IPS  {:.2}x", fps, ips, speed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_show_speed_against_60hz() {
+        assert_eq!(stats_text(60.0, 480.0), "60 FPS  480 IPS  1.00x");
+        assert_eq!(stats_text(30.0, 240.0), "30 FPS  240 IPS  0.50x");
+
+        let mut osd = Osd::new(false);
+        assert_eq!(osd.overlay(false).stats, None);
+        osd.toggle_stats();
+        osd.notify("State 3 saved".to_string());
+        let overlay = osd.overlay(true);
+        assert!(overlay.stats.is_some() && overlay.paused);
+        assert_eq!(overlay.message.as_deref(), Some("State 3 saved"));
+    }
+}
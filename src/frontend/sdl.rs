@@ -0,0 +1,68 @@
+use chip_8::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+use crate::drivers::{DisplayDriver, InputDriver, AudioDriver};
+use crate::beeper::Tone;
+use crate::palette::Palette;
+use super::browser::{self, View};
+use super::{Frontend, Hotkey, Overlay};
+
+/// Window, keyboard and audio through SDL2.
+pub struct SdlFrontend {
+    display_driver: DisplayDriver,
+    input_driver: InputDriver,
+    audio_driver: AudioDriver,
+}
+
+impl SdlFrontend {
+    pub fn new(scale: u32, integer_scaling: bool, tone: Tone) -> Result<Self, String> {
+        let sdl_context = sdl2::init()?;
+
+        Ok(SdlFrontend {
+            display_driver: DisplayDriver::new(&sdl_context, scale, integer_scaling),
+            input_driver: InputDriver::new(&sdl_context),
+            audio_driver: AudioDriver::new(&sdl_context, tone),
+        })
+    }
+}
+
+impl Frontend for SdlFrontend {
+    fn process_inputs(&mut self, hotkeys: &mut Vec<Hotkey>) -> Result<Vec<String>, ()> {
+        self.input_driver.process_inputs(hotkeys)
+    }
+
+    fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+        self.display_driver.draw(display);
+    }
+
+    fn draw_browser(&mut self, view: &View) {
+        self.display_driver.draw(&browser::render(view));
+    }
+
+    fn set_overlay(&mut self, overlay: &Overlay) {
+        self.display_driver.set_overlay(overlay.clone());
+    }
+
+    fn set_sound_timer(&mut self, value: u8) {
+        self.audio_driver.set_sound_timer(value);
+    }
+
+    fn set_sound_rate(&mut self, rate: f32) {
+        self.audio_driver.set_rate(rate);
+    }
+
+    fn set_tone(&mut self, tone: &Tone) {
+        self.audio_driver.set_tone(*tone);
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.display_driver.set_palette(*palette);
+    }
+
+    fn set_phosphor(&mut self, decay: Option<f32>) {
+        self.display_driver.set_phosphor(decay);
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        self.display_driver.toggle_fullscreen();
+    }
+}
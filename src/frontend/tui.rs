@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyEventState, KeyModifiers};
+use crossterm::event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use chip_8::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+use crate::keymap::char_key_name;
+use crate::palette::{Palette, Rgb};
+use super::browser::{self, View};
+use super::{Frontend, Hotkey, Overlay};
+
+// Most terminals only report presses (and auto-repeats), never releases, so a key
+// counts as held for this long after the last event for it.
+const KEY_HOLD_TIME: Duration = Duration::from_millis(250);
+
+const BROWSER_LIST_WIDTH: u16 = 40; // columns, with the preview to the right
+
+type Frame = [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT];
+
+/// Draws the display in the terminal using half-block characters, two pixels per cell.
+pub struct TuiFrontend {
+    stdout: Stdout,
+    color: bool, // draw in the palette's colours rather than the terminal's own
+    palette: Palette,
+    key_releases: bool, // terminal reports key release events
+    pressed_at: HashMap<String, Instant>, // host keys held down, by when they were last reported
+    frame: Frame,
+    drawn: Option<Frame>, // what is currently on the terminal
+    browser_drawn: Option<(usize, u16, u16)>, // browser selection and terminal size the list was drawn for
+    overlay: Overlay,
+    overlay_drawn: Option<Overlay>, // what is currently on the lines under the display
+}
+
+impl TuiFrontend {
+    pub fn new(color: bool) -> io::Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+
+        let key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_releases {
+            // Disambiguating escape codes also tells keypad keys apart from the rest of the keyboard
+            let flags = KeyboardEnhancementFlags::REPORT_EVENT_TYPES | KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES;
+            execute!(stdout, PushKeyboardEnhancementFlags(flags))?;
+        }
+
+        Ok(TuiFrontend {
+            stdout,
+            color,
+            palette: Palette::default(),
+            key_releases,
+            pressed_at: HashMap::new(),
+            frame: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
+            drawn: None,
+            browser_drawn: None,
+            overlay: Overlay::default(),
+            overlay_drawn: None,
+        })
+    }
+
+    // Draws the frame with its top left corner at cell (`x`, `y`), only writing cells that changed.
+    fn present(&mut self, x: u16, y: u16) -> io::Result<()> {
+        let mut current_colors = None;
+
+        for cell_row in 0..CHIP8_HEIGHT / 2 {
+            let top = &self.frame[cell_row * 2];
+            let bottom = &self.frame[cell_row * 2 + 1];
+            let mut cursor_in_place = false;
+
+            for column in 0..CHIP8_WIDTH {
+                let unchanged = self.drawn.is_some_and(|drawn| {
+                    drawn[cell_row * 2][column] == top[column] && drawn[cell_row * 2 + 1][column] == bottom[column]
+                });
+                if unchanged {
+                    cursor_in_place = false;
+                    continue;
+                }
+
+                if !cursor_in_place {
+                    queue!(self.stdout, MoveTo(x + column as u16, y + cell_row as u16))?;
+                    cursor_in_place = true;
+                }
+                if self.color {
+                    // The upper half takes the foreground colour and the lower half the background,
+                    // so any pair of palette colours can share a cell
+                    let colors = (self.palette.color(top[column]), self.palette.color(bottom[column]));
+                    if current_colors != Some(colors) {
+                        queue!(self.stdout, SetForegroundColor(color(colors.0)), SetBackgroundColor(color(colors.1)))?;
+                        current_colors = Some(colors);
+                    }
+                    queue!(self.stdout, Print('▀'))?;
+                } else {
+                    queue!(self.stdout, Print(half_block(top[column], bottom[column])))?;
+                }
+            }
+        }
+
+        if self.color {
+            queue!(self.stdout, ResetColor)?;
+        }
+        self.stdout.flush()?;
+        self.drawn = Some(self.frame);
+        Ok(())
+    }
+
+    // The overlay goes on the two lines under the display, since text can't be drawn over it in a terminal.
+    fn present_overlay(&mut self) -> io::Result<()> {
+        if self.overlay_drawn.as_ref() == Some(&self.overlay) {
+            return Ok(());
+        }
+        let (width, _) = terminal::size()?;
+        let stats_row = (CHIP8_HEIGHT / 2) as u16;
+        queue!(self.stdout, MoveTo(0, stats_row), Clear(ClearType::UntilNewLine))?;
+        if let Some(stats) = &self.overlay.stats {
+            queue!(self.stdout, Print(browser::fit(stats, CHIP8_WIDTH - 8)))?;
+        }
+        if self.overlay.paused {
+            queue!(self.stdout, MoveTo(CHIP8_WIDTH as u16 - 6, stats_row), Print("PAUSED"))?;
+        }
+        queue!(self.stdout, MoveTo(0, stats_row + 1), Clear(ClearType::UntilNewLine))?;
+        if let Some(message) = &self.overlay.message {
+            queue!(self.stdout, Print(browser::fit(message, width as usize)))?;
+        }
+        self.stdout.flush()?;
+        self.overlay_drawn = Some(self.overlay.clone());
+        Ok(())
+    }
+
+    fn present_browser(&mut self, view: &View) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let state = (view.selected, width, height);
+        if self.browser_drawn != Some(state) {
+            // Only the preview changes every frame, so the rest is drawn when the selection moves
+            queue!(self.stdout, ResetColor, Clear(ClearType::All))?;
+            let count = format!("{}/{}", (view.selected + 1).min(view.entries.len()), view.entries.len());
+            let header = browser::fit(&format!("ROMs in {}", view.dir.display()), BROWSER_LIST_WIDTH as usize);
+            queue!(self.stdout, MoveTo(0, 0), Print(header), MoveTo(BROWSER_LIST_WIDTH + 2, 0), Print(count))?;
+
+            let rows = height.saturating_sub(2) as usize;
+            for (row, index) in view.visible(rows).enumerate() {
+                let title = browser::fit(&view.entries[index].title, BROWSER_LIST_WIDTH as usize);
+                queue!(self.stdout, MoveTo(0, 1 + row as u16))?;
+                if index == view.selected {
+                    let padded = format!("{:width$}", title, width = BROWSER_LIST_WIDTH as usize);
+                    queue!(self.stdout, SetAttribute(Attribute::Reverse), Print(padded), SetAttribute(Attribute::Reset))?;
+                } else {
+                    queue!(self.stdout, Print(title))?;
+                }
+            }
+            if view.entries.is_empty() {
+                queue!(self.stdout, MoveTo(0, 1), Print("No ROMs found"))?;
+            }
+
+            if let Some(entry) = view.selected() {
+                let details_top = 2 + (CHIP8_HEIGHT / 2) as u16;
+                let file_name = entry.path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+                let mut lines = browser::wrap(&entry.title, CHIP8_WIDTH);
+                lines.push(String::new());
+                lines.push(file_name);
+                lines.push(format!("{} bytes, {}", entry.size, entry.profile.platform.unwrap_or_default()));
+                for (i, line) in lines.iter().enumerate() {
+                    queue!(self.stdout, MoveTo(BROWSER_LIST_WIDTH + 2, details_top + i as u16), Print(line))?;
+                }
+            }
+            queue!(self.stdout, MoveTo(0, height.saturating_sub(1)), Print("Enter to play, Esc to quit, arrows to choose"))?;
+
+            self.browser_drawn = Some(state);
+            self.drawn = None;
+        }
+
+        self.frame = *view.preview;
+        if self.drawn != Some(self.frame) {
+            self.present(BROWSER_LIST_WIDTH + 2, 1)?;
+        }
+        self.stdout.flush()
+    }
+}
+
+impl Frontend for TuiFrontend {
+    fn process_inputs(&mut self, hotkeys: &mut Vec<Hotkey>) -> Result<Vec<String>, ()> {
+        while event::poll(Duration::from_secs(0)).unwrap_or(false) {
+            let key_event = match event::read() {
+                Ok(Event::Key(key_event)) => key_event,
+                Ok(Event::Resize(..)) => {
+                    self.drawn = None; // terminal contents are gone, redraw everything
+                    self.browser_drawn = None;
+                    self.overlay_drawn = None;
+                    continue;
+                },
+                _ => continue,
+            };
+
+            let ctrl_c = key_event.code == KeyCode::Char('c') && key_event.modifiers.contains(KeyModifiers::CONTROL);
+            if key_event.code == KeyCode::Esc || ctrl_c {
+                return Err(());
+            }
+
+            if key_event.kind == KeyEventKind::Press {
+                if let Some(hotkey) = hotkey(key_event.code, key_event.modifiers) {
+                    hotkeys.push(hotkey);
+                }
+            }
+
+            if let Some(name) = host_key_name(key_event.code, key_event.state) {
+                if key_event.kind == KeyEventKind::Release {
+                    self.pressed_at.remove(&name);
+                } else {
+                    self.pressed_at.insert(name, Instant::now());
+                }
+            }
+        }
+
+        if !self.key_releases {
+            self.pressed_at.retain(|_, pressed_at| pressed_at.elapsed() < KEY_HOLD_TIME);
+        }
+        Ok(self.pressed_at.keys().cloned().collect())
+    }
+
+    fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+        if self.browser_drawn.take().is_some() {
+            let _ = execute!(self.stdout, ResetColor, Clear(ClearType::All));
+            self.drawn = None;
+            self.overlay_drawn = None;
+        }
+        self.frame = *display;
+        if self.drawn != Some(self.frame) {
+            let _ = self.present(0, 0);
+        }
+        let _ = self.present_overlay();
+    }
+
+    fn draw_browser(&mut self, view: &View) {
+        let _ = self.present_browser(view);
+    }
+
+    fn set_overlay(&mut self, overlay: &Overlay) {
+        self.overlay = overlay.clone();
+    }
+
+    fn set_palette(&mut self, palette: &Palette) {
+        self.palette = *palette;
+        self.drawn = None;
+    }
+}
+
+impl Drop for TuiFrontend {
+    fn drop(&mut self) {
+        if self.key_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn half_block(top: u8, bottom: u8) -> char {
+    match (top > 0, bottom > 0) {
+        (false, false) => ' ',
+        (true, false) => '▀',
+        (false, true) => '▄',
+        (true, true) => '█',
+    }
+}
+
+fn color(rgb: Rgb) -> Color {
+    Color::Rgb { r: rgb.0, g: rgb.1, b: rgb.2 }
+}
+
+fn hotkey(code: KeyCode, modifiers: KeyModifiers) -> Option<Hotkey> {
+    match code {
+        KeyCode::F(1) => Some(Hotkey::OpenBrowser),
+        KeyCode::F(2) => Some(Hotkey::CyclePalette),
+        KeyCode::F(3) => Some(Hotkey::TogglePhosphor),
+        KeyCode::F(4) => Some(Hotkey::CyclePhosphorDecay),
+        KeyCode::F(5) => Some(Hotkey::SaveState),
+        KeyCode::F(6) => Some(Hotkey::NextStateSlot),
+        KeyCode::F(7) => Some(Hotkey::LoadState),
+        KeyCode::F(8) => Some(Hotkey::RebindKeys),
+        KeyCode::F(9) => Some(Hotkey::ToggleGifRecording),
+        KeyCode::F(12) => Some(Hotkey::Screenshot),
+        KeyCode::Char('`') => Some(Hotkey::ToggleStats),
+        KeyCode::Char(' ') => Some(Hotkey::TogglePause),
+        KeyCode::Char('\\') => Some(Hotkey::AdvanceFrame),
+        KeyCode::Char('[') => Some(Hotkey::CycleSlowMotion),
+        KeyCode::F(10) => Some(Hotkey::ToggleMute),
+        KeyCode::F(11) if modifiers.contains(KeyModifiers::SHIFT) => Some(Hotkey::SaveRomConfig),
+        KeyCode::F(11) => Some(Hotkey::SaveConfig),
+        KeyCode::PageUp => Some(Hotkey::VolumeUp),
+        KeyCode::PageDown => Some(Hotkey::VolumeDown),
+        KeyCode::Enter if modifiers.contains(KeyModifiers::ALT) => Some(Hotkey::ToggleFullscreen),
+        _ => None,
+    }
+}
+
+// Names keys the way `Keymap` expects.
+fn host_key_name(code: KeyCode, state: KeyEventState) -> Option<String> {
+    let keypad = state.contains(KeyEventState::KEYPAD);
+    let name = match code {
+        KeyCode::Char(c) if keypad => format!("kp{}", c),
+        KeyCode::Char(c) => char_key_name(c),
+        KeyCode::Enter if keypad => "kpenter".to_string(),
+        KeyCode::Enter => "enter".to_string(),
+        KeyCode::Up => "up".to_string(),
+        KeyCode::Down => "down".to_string(),
+        KeyCode::Left => "left".to_string(),
+        KeyCode::Right => "right".to_string(),
+        KeyCode::Tab => "tab".to_string(),
+        KeyCode::Backspace => "backspace".to_string(),
+        KeyCode::F(n) => format!("f{}", n),
+        _ => return None,
+    };
+    Some(name)
+}
@@ -0,0 +1,141 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use gif::{Encoder, Frame, Repeat};
+
+use crate::palette::Palette;
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+/// Records presented frames to an animated GIF.
+///
+/// GIF delays are in hundredths of a second, which 1/60 doesn't divide, so each frame's delay is
+/// worked out from when it starts and ends in the recording and the rounding never adds up.
+/// Identical consecutive frames are merged into one longer frame.
+pub struct GifRecorder {
+    path: PathBuf,
+    encoder: Encoder<BufWriter<File>>,
+    scale: usize,
+    width: usize,
+    height: usize,
+    pending: Vec<u8>, // the last distinct frame, unscaled, waiting to find out how long it lasts
+    pending_start: u64, // frame number `pending` was first shown on
+    frames: u64, // frames recorded so far
+}
+
+impl GifRecorder {
+    pub fn create(path: &Path, palette: &Palette, width: usize, height: usize, scale: u32) -> Result<Self, Box<dyn Error>> {
+        let scale = scale as usize;
+        let global_palette: Vec<u8> = palette.colors.iter().flat_map(|color| vec![color.0, color.1, color.2]).collect();
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(file, (width * scale) as u16, (height * scale) as u16, &global_palette)?;
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        Ok(GifRecorder {
+            path: path.to_path_buf(),
+            encoder,
+            scale,
+            width,
+            height,
+            pending: Vec::new(),
+            pending_start: 0,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn add_frame<R: AsRef<[u8]>>(&mut self, display: &[R]) -> Result<(), Box<dyn Error>> {
+        let frame: Vec<u8> = display.iter()
+            .take(self.height)
+            .flat_map(|row| row.as_ref().iter().take(self.width).map(|&pixel| pixel & 0b11))
+            .collect();
+
+        let too_long = centiseconds(self.frames + 1) - centiseconds(self.pending_start) > u16::MAX as u64;
+        if frame != self.pending || too_long {
+            self.write_pending()?;
+            self.pending = frame;
+            self.pending_start = self.frames;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes out the last frame and the end of the file.
+    pub fn finish(mut self) -> Result<PathBuf, Box<dyn Error>> {
+        self.write_pending()?;
+        Ok(self.path) // the encoder writes the trailer when it's dropped
+    }
+
+    fn write_pending(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.frames == self.pending_start {
+            return Ok(()); // nothing recorded yet
+        }
+
+        let mut buffer = Vec::with_capacity(self.pending.len() * self.scale * self.scale);
+        for row in self.pending.chunks(self.width) {
+            let line: Vec<u8> = row.iter().flat_map(|&index| vec![index; self.scale]).collect();
+            for _ in 0..self.scale {
+                buffer.extend_from_slice(&line);
+            }
+        }
+
+        let frame = Frame {
+            delay: (centiseconds(self.frames) - centiseconds(self.pending_start)) as u16,
+            width: (self.width * self.scale) as u16,
+            height: (self.height * self.scale) as u16,
+            buffer: Cow::Owned(buffer),
+            ..Frame::default()
+        };
+        self.encoder.write_frame(&frame)?;
+        Ok(())
+    }
+}
+
+// Time from the start of the recording to the start of a frame, rounded to hundredths of a second.
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn delays_add_up_to_real_time() {
+        // Any run of frames lasts as long as it would at 60Hz, to the nearest hundredth of a second
+        let delays: Vec<u64> = (0..6).map(|frame| centiseconds(frame + 1) - centiseconds(frame)).collect();
+        assert_eq!(delays, vec![2, 1, 2, 2, 1, 2]);
+        assert_eq!(delays.iter().sum::<u64>(), 10);
+    }
+
+    #[test]
+    fn identical_frames_are_merged() {
+        let path = env::temp_dir().join("chip8_gif_recorder_test.gif");
+        let mut recorder = GifRecorder::create(&path, &Palette::default(), 2, 1, 2).unwrap();
+        for _ in 0..3 {
+            recorder.add_frame(&[[1u8, 0]]).unwrap();
+        }
+        recorder.add_frame(&[[0u8, 1]]).unwrap();
+        recorder.finish().unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer.to_vec()));
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(frames, vec![(5, vec![1, 1, 0, 0, 1, 1, 0, 0]), (2, vec![0, 0, 1, 1, 0, 0, 1, 1])]);
+    }
+}
@@ -0,0 +1,16 @@
+//! What a host provides to run the interpreter a frame at a time with `Chip8::run_frame`.
+
+use crate::chip8::NUM_KEYS;
+use crate::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+/// Shows the display, such as on a small OLED panel.
+pub trait Screen {
+	/// Called once a frame with the whole display, one byte per pixel. Pixels are 0 when off.
+	fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]);
+}
+
+/// Reads the 16 key hex keypad.
+pub trait Keypad {
+	/// The keys held down, indexed by their hex value.
+	fn keys(&mut self) -> [bool; NUM_KEYS];
+}
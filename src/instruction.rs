@@ -1,7 +1,10 @@
+use core::fmt;
+
 pub type Address = u16;
 pub type Register = u8;
 
-#[derive(Debug)]
+/// A decoded instruction, named after what it does. Registers are numbered 0 to F.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
 	CLS(),
 	RET(),
@@ -18,9 +21,9 @@ pub enum Instruction {
 	XOR(Register, Register),
 	ADD(Register, Register), // VF set to 1 if overflow
 	SUB(Register, Register), // VF set to 1 if negative overflow
-	SHR(Register), // Shift right 1 (binary divide by 2)
+	SHR(Register, Register), // Shift right 1 (binary divide by 2). Some interpreters shift the 2nd REG into the 1st
 	SUBN(Register, Register), // 2nd REG - 1st REG set VF if result negative
-	SHL(Register), // Shift left 1 (binary multiply by 2)
+	SHL(Register, Register), // Shift left 1 (binary multiply by 2). Some interpreters shift the 2nd REG into the 1st
 	SNE(Register, Register), // Skip PC if not equal
 	LDI(Address), // Load i_reg with addr
 	JPV0(Address), // JMP to address + V0
@@ -41,7 +44,92 @@ pub enum Instruction {
 	LR(Register) // Load Registers
 }
 
-#[derive(Debug)]
+impl Instruction {
+	/// The variant's name, like `DRW`, which tells apart instructions that share a mnemonic.
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Instruction::CLS(..) => "CLS",
+			Instruction::RET(..) => "RET",
+			Instruction::JMP(..) => "JMP",
+			Instruction::CALL(..) => "CALL",
+			Instruction::SEQB(..) => "SEQB",
+			Instruction::SNEQB(..) => "SNEQB",
+			Instruction::SRER(..) => "SRER",
+			Instruction::LBR(..) => "LBR",
+			Instruction::ABR(..) => "ABR",
+			Instruction::LRR(..) => "LRR",
+			Instruction::OR(..) => "OR",
+			Instruction::AND(..) => "AND",
+			Instruction::XOR(..) => "XOR",
+			Instruction::ADD(..) => "ADD",
+			Instruction::SUB(..) => "SUB",
+			Instruction::SHR(..) => "SHR",
+			Instruction::SUBN(..) => "SUBN",
+			Instruction::SHL(..) => "SHL",
+			Instruction::SNE(..) => "SNE",
+			Instruction::LDI(..) => "LDI",
+			Instruction::JPV0(..) => "JPV0",
+			Instruction::RND(..) => "RND",
+			Instruction::DRW(..) => "DRW",
+			Instruction::SKP(..) => "SKP",
+			Instruction::SKNP(..) => "SKNP",
+			Instruction::LDDV(..) => "LDDV",
+			Instruction::LDK(..) => "LDK",
+			Instruction::LDVD(..) => "LDVD",
+			Instruction::LDST(..) => "LDST",
+			Instruction::ADDI(..) => "ADDI",
+			Instruction::LDS(..) => "LDS",
+			Instruction::BCD(..) => "BCD",
+			Instruction::SR(..) => "SR",
+			Instruction::LR(..) => "LR",
+		}
+	}
+}
+
+/// Writes the instruction in the usual assembler syntax, like `LD V3, 0x0A` or `DRW V0, V1, 5`.
+impl fmt::Display for Instruction {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Instruction::CLS() => write!(f, "CLS"),
+			Instruction::RET() => write!(f, "RET"),
+			Instruction::JMP(addr) => write!(f, "JP {:#05X}", addr),
+			Instruction::CALL(addr) => write!(f, "CALL {:#05X}", addr),
+			Instruction::SEQB(x, kk) => write!(f, "SE V{:X}, {:#04X}", x, kk),
+			Instruction::SNEQB(x, kk) => write!(f, "SNE V{:X}, {:#04X}", x, kk),
+			Instruction::SRER(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+			Instruction::LBR(x, kk) => write!(f, "LD V{:X}, {:#04X}", x, kk),
+			Instruction::ABR(x, kk) => write!(f, "ADD V{:X}, {:#04X}", x, kk),
+			Instruction::LRR(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+			Instruction::OR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+			Instruction::AND(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+			Instruction::XOR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+			Instruction::ADD(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+			Instruction::SUB(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+			Instruction::SHR(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+			Instruction::SUBN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+			Instruction::SHL(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+			Instruction::SNE(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+			Instruction::LDI(addr) => write!(f, "LD I, {:#05X}", addr),
+			Instruction::JPV0(addr) => write!(f, "JP V0, {:#05X}", addr),
+			Instruction::RND(x, kk) => write!(f, "RND V{:X}, {:#04X}", x, kk),
+			Instruction::DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+			Instruction::SKP(x) => write!(f, "SKP V{:X}", x),
+			Instruction::SKNP(x) => write!(f, "SKNP V{:X}", x),
+			Instruction::LDDV(x) => write!(f, "LD V{:X}, DT", x),
+			Instruction::LDK(x) => write!(f, "LD V{:X}, K", x),
+			Instruction::LDVD(x) => write!(f, "LD DT, V{:X}", x),
+			Instruction::LDST(x) => write!(f, "LD ST, V{:X}", x),
+			Instruction::ADDI(x) => write!(f, "ADD I, V{:X}", x),
+			Instruction::LDS(x) => write!(f, "LD F, V{:X}", x),
+			Instruction::BCD(x) => write!(f, "LD B, V{:X}", x),
+			Instruction::SR(x) => write!(f, "LD [I], V{:X}", x),
+			Instruction::LR(x) => write!(f, "LD V{:X}, [I]", x),
+		}
+	}
+}
+
+/// A raw 16-bit opcode, as fetched from memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OpCodeInstruction {
 	value: u16, // All instructions are 2 bytes long BigEndian style. The first byte should be loaded at even addresses in memory. 
 				// Sprite data should be padded to maintain alignment.
@@ -52,6 +140,7 @@ impl OpCodeInstruction {
 		OpCodeInstruction { value }
 	}
 
+	/// Decodes the opcode, or gives `None` if it isn't a CHIP-8 instruction.
 	pub fn process_opcode(&self) -> Option<Instruction> {
 
 		let i = self.i();
@@ -84,9 +173,9 @@ impl OpCodeInstruction {
 					0x3 => Some(Instruction::XOR(x, y)),
 					0x4 => Some(Instruction::ADD(x, y)),
 					0x5 => Some(Instruction::SUB(x, y)),
-					0x6 => Some(Instruction::SHR(x)),
+					0x6 => Some(Instruction::SHR(x, y)),
 					0x7 => Some(Instruction::SUBN(x, y)),
-					0xE => Some(Instruction::SHL(x)),
+					0xE => Some(Instruction::SHL(x, y)),
 					_ => None,
 				}
 			}
@@ -146,8 +235,6 @@ impl OpCodeInstruction {
 }
 
 
-#[cfg(test)]
-
 // u16 == 39854 == 1001101110101110
 // nnn == 2990  == 	   101110101110
 // n   ==  14   ==        	   1110
@@ -155,6 +242,7 @@ impl OpCodeInstruction {
 // y   ==  10	==	        1010
 // kk  ==  174  ==         10101110
 
+#[cfg(test)]
 mod tests {
 	use super::*;
 
@@ -205,4 +293,23 @@ mod tests {
 		let y = ins.kk();
 		assert!(y == 174);
 	}
+
+	#[test]
+	fn assembler_syntax() {
+		let text = |opcode| OpCodeInstruction::new(opcode).process_opcode().unwrap().to_string();
+		assert_eq!(text(0x00E0), "CLS");
+		assert_eq!(text(0x1228), "JP 0x228");
+		assert_eq!(text(0x6A0F), "LD VA, 0x0F");
+		assert_eq!(text(0xD015), "DRW V0, V1, 5");
+		assert_eq!(text(0xF355), "LD [I], V3");
+	}
+
+	#[test]
+	fn names() {
+		let name = |opcode| OpCodeInstruction::new(opcode).process_opcode().unwrap().name();
+		assert_eq!(name(0x00E0), "CLS");
+		assert_eq!(name(0x6A0F), "LBR");
+		assert_eq!(name(0x8A14), "ADD");
+		assert_eq!(name(0xF355), "SR");
+	}
 }
\ No newline at end of file
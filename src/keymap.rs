@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use chip_8::chip8::NUM_KEYS;
+use chip_8::font::FONT_SET;
+use chip_8::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+use crate::config::KeymapConfig;
+
+/// The CHIP-8 keys in the order they sit on the keypad, left to right and top to bottom:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+pub const KEYPAD_ORDER: [usize; NUM_KEYS] = [0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF];
+
+const DEFAULT_PRESET: &str = "qwerty";
+
+/// Held down to fast-forward, rather than pressed like the other hotkeys.
+pub const FAST_FORWARD_KEY: &str = "tab";
+
+/// Host keys the hotkeys use besides the function keys: stats, pause, frame advance and slow motion,
+/// then fast-forward.
+const HOTKEYS: [&str; 5] = ["`", "space", "\\", "[", FAST_FORWARD_KEY];
+
+/// Host keys for each position in `KEYPAD_ORDER`. Most keep the keypad's shape on the left
+/// hand side of the keyboard.
+const PRESETS: [(&str, [&str; NUM_KEYS]); 5] = [
+    ("qwerty", ["1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "z", "x", "c", "v"]),
+    ("azerty", ["1", "2", "3", "4", "a", "z", "e", "r", "q", "s", "d", "f", "w", "x", "c", "v"]),
+    ("qwertz", ["1", "2", "3", "4", "q", "w", "e", "r", "a", "s", "d", "f", "y", "x", "c", "v"]),
+    ("dvorak", ["1", "2", "3", "4", "'", ",", ".", "p", "a", "o", "e", "u", ";", "q", "j", "k"]),
+    // The digit keys keep the keypad's shape on the numeric keypad, so 2/4/6/8 are up/left/right/down
+    ("numpad", ["kp7", "kp8", "kp9", "kp*", "kp4", "kp5", "kp6", "kp-", "kp1", "kp2", "kp3", "kp+", "kp0", "kp.", "kpenter", "kp/"]),
+];
+
+/// Maps host keys to CHIP-8 keys. Host keys are named by the character they type in lower case
+/// (`"q"`, `"1"`, `";"`) or otherwise by names like `"space"`, `"up"`, `"enter"` and `"kp7"`.
+/// A CHIP-8 key can have any number of host keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    bindings: BTreeMap<String, usize>,
+}
+
+impl Keymap {
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let (_, keys) = PRESETS.iter().find(|(preset, _)| *preset == name)?;
+        let bindings = keys.iter()
+            .zip(KEYPAD_ORDER.iter())
+            .map(|(&host_key, &key)| (host_key.to_string(), key))
+            .collect();
+        Some(Keymap { bindings })
+    }
+
+    /// Builds the keymap from the config file, with the ROM's own section applied on top.
+    /// `preset`, when given, replaces the preset named in the config.
+    pub fn from_config(preset: Option<&str>, global: Option<&KeymapConfig>, rom: Option<&KeymapConfig>) -> Result<Keymap, String> {
+        let preset = preset
+            .or_else(|| rom.and_then(|rom| rom.preset.as_deref()))
+            .or_else(|| global.and_then(|global| global.preset.as_deref()))
+            .unwrap_or(DEFAULT_PRESET);
+        let mut keymap = Keymap::preset(preset).ok_or_else(|| format!("unknown keymap preset '{}'", preset))?;
+
+        for config in global.into_iter().chain(rom) {
+            for (key, host_keys) in config.keys.iter() {
+                let key = usize::from_str_radix(key, 16).ok().filter(|&key| key < NUM_KEYS)
+                    .ok_or_else(|| format!("'{}' isn't a CHIP-8 key, expected 0 to F", key))?;
+                keymap.clear(key);
+                for host_key in host_keys {
+                    let host_key = host_key.to_lowercase();
+                    if is_hotkey(&host_key) {
+                        return Err(format!("'{}' is a hotkey, so it can't be bound to the keypad", host_key));
+                    }
+                    keymap.bind(&host_key, key);
+                }
+            }
+        }
+        Ok(keymap)
+    }
+
+    /// The keymap as it would be written in the config file, with every key listed so it reads back
+    /// exactly whatever the preset.
+    pub fn to_config(&self) -> KeymapConfig {
+        let mut keys: BTreeMap<String, Vec<String>> = (0..NUM_KEYS).map(|key| (format!("{:X}", key), Vec::new())).collect();
+        for (host_key, &key) in self.bindings.iter() {
+            if let Some(host_keys) = keys.get_mut(&format!("{:X}", key)) {
+                host_keys.push(host_key.clone());
+            }
+        }
+        KeymapConfig { preset: None, keys }
+    }
+
+    /// Binds another host key to a CHIP-8 key, taking it away from whatever it was bound to before.
+    pub fn bind(&mut self, host_key: &str, key: usize) {
+        self.bindings.insert(host_key.to_string(), key);
+    }
+
+    /// Makes `host_key` the only host key for a CHIP-8 key, unless it's a hotkey. Returns whether it was bound.
+    pub fn rebind(&mut self, host_key: &str, key: usize) -> bool {
+        if is_hotkey(host_key) {
+            return false;
+        }
+        self.clear(key);
+        self.bind(host_key, key);
+        true
+    }
+
+    pub fn clear(&mut self, key: usize) {
+        self.bindings.retain(|_, bound| *bound != key);
+    }
+
+    /// The state of the CHIP-8 keys when `held` host keys are down.
+    pub fn keys<S: AsRef<str>>(&self, held: &[S]) -> [bool; NUM_KEYS] {
+        let mut keys = [false; NUM_KEYS];
+        for host_key in held {
+            if let Some(&key) = self.bindings.get(host_key.as_ref()) {
+                keys[key] = true;
+            }
+        }
+        keys
+    }
+}
+
+/// Names a host key from the character it types.
+pub fn char_key_name(c: char) -> String {
+    match c {
+        ' ' => "space".to_string(),
+        _ => c.to_lowercase().collect(),
+    }
+}
+
+/// Whether a host key drives one of the emulator's hotkeys, which would fire along with the keypad
+/// key if it were bound to one, so it can't be. These are the function keys and `HOTKEYS`.
+pub fn is_hotkey(host_key: &str) -> bool {
+    let function_key = host_key.len() > 1 && host_key.starts_with('f') && host_key[1..].chars().all(|c| c.is_ascii_digit());
+    function_key || HOTKEYS.contains(&host_key)
+}
+
+/// The rebinding screen: the keypad drawn in the CHIP-8 font with the key being bound highlighted.
+pub fn rebind_screen(position: usize) -> [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT] {
+    const CELL_WIDTH: usize = CHIP8_WIDTH / 4;
+    const CELL_HEIGHT: usize = CHIP8_HEIGHT / 4;
+    let mut screen = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+
+    for (cell, &key) in KEYPAD_ORDER.iter().enumerate() {
+        let left = (cell % 4) * CELL_WIDTH;
+        let top = (cell / 4) * CELL_HEIGHT;
+        let highlight = (cell == position) as u8;
+
+        for y in 0..CELL_HEIGHT {
+            for x in 0..CELL_WIDTH {
+                screen[top + y][left + x] = highlight;
+            }
+        }
+
+        // Font characters are 4 pixels wide and 5 tall, in the high nibble of 5 bytes
+        let glyph = &FONT_SET[key * 5..key * 5 + 5];
+        for (y, &row) in glyph.iter().enumerate() {
+            for x in 0..4 {
+                let pixel = (row >> (7 - x)) & 1;
+                screen[top + 1 + y][left + 6 + x] ^= pixel;
+            }
+        }
+    }
+    screen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_cover_every_key() {
+        for (name, _) in PRESETS.iter() {
+            let keymap = Keymap::preset(name).unwrap();
+            let mut bound = [false; NUM_KEYS];
+            for &key in keymap.bindings.values() {
+                bound[key] = true;
+            }
+            assert!(bound.iter().all(|&bound| bound), "{} leaves keys unbound", name);
+        }
+    }
+
+    #[test]
+    fn config_overrides_preset() {
+        let mut global = KeymapConfig::default();
+        global.keys.insert("5".to_string(), vec!["W".to_string(), "up".to_string()]);
+        let rom = KeymapConfig { preset: Some("azerty".to_string()), ..KeymapConfig::default() };
+
+        let keymap = Keymap::from_config(None, Some(&global), Some(&rom)).unwrap();
+        assert!(keymap.keys(&["up"])[0x5]);
+        assert!(keymap.keys(&["w"])[0x5]);
+        assert!(!keymap.keys(&["z"])[0x5]); // AZERTY's own binding for 5 was replaced
+        assert!(keymap.keys(&["a"])[0x4]);
+    }
+
+    #[test]
+    fn saved_keymap_reads_back() {
+        let mut keymap = Keymap::preset("dvorak").unwrap();
+        keymap.clear(0x5);
+        keymap.bind("up", 0xA);
+        assert_eq!(Keymap::from_config(None, Some(&keymap.to_config()), None), Ok(keymap));
+    }
+
+    #[test]
+    fn hotkeys() {
+        assert!(is_hotkey("f12"));
+        assert!(is_hotkey("`"));
+        assert!(!is_hotkey("f"));
+        assert!(!is_hotkey("fx"));
+    }
+
+    #[test]
+    fn rebinding_to_a_hotkey_is_refused() {
+        let mut keymap = Keymap::preset("qwerty").unwrap();
+        assert!(!keymap.rebind("tab", 0x5));
+        assert!(!keymap.rebind("space", 0x5));
+        assert!(keymap.keys(&["w"])[0x5]);
+        assert!(keymap.rebind("up", 0x5));
+        assert!(keymap.keys(&["up"])[0x5]);
+        assert!(!keymap.keys(&["w"])[0x5]);
+    }
+
+    #[test]
+    fn config_cant_bind_a_hotkey() {
+        let mut global = KeymapConfig::default();
+        global.keys.insert("5".to_string(), vec!["Tab".to_string()]);
+        assert!(Keymap::from_config(None, Some(&global), None).is_err());
+    }
+}
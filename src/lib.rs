@@ -0,0 +1,51 @@
+//! A CHIP-8 interpreter, without any display, sound or input of its own.
+//!
+//! Load a program into a [`Chip8`], then for each 60th of a second set the keys that are held,
+//! run some instructions with [`Chip8::cycle`], tick the timers and show [`Chip8::display`].
+//! How programs behave can be matched to a machine with [`Platform`], or to taste with [`Quirks`].
+//!
+//! ```
+//! use chip_8::{Chip8, Platform};
+//!
+//! // LD V0, 0x05 then JP 0x202, looping on the jump
+//! let mut chip8 = Chip8::with_seed(vec![0x60, 0x05, 0x12, 0x02], 0);
+//! chip8.set_platform(Platform::CosmacVip);
+//! chip8.set_keys([false; chip_8::chip8::NUM_KEYS]);
+//! for _ in 0..8 {
+//!     chip8.cycle()?;
+//! }
+//! chip8.tick_timers();
+//! assert_eq!(chip8.registers()[0], 5);
+//! assert_eq!(chip8.display().len(), chip_8::CHIP8_HEIGHT);
+//! # Ok::<(), chip_8::Chip8Error>(())
+//! ```
+//!
+//! Without the `std` feature the crate is `no_std` and never allocates, for microcontrollers. The
+//! host then supplies the keypad and screen through [`Keypad`] and [`Screen`] for
+//! [`Chip8::run_frame`], and can supply its own [`Rng`] for `RND` with [`Chip8::with_rng`].
+//!
+//! With `std`, `beeper` and `palette` have the emulator program's sound and colours, for other
+//! frontends to share.
+
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+#[cfg(feature = "std")]
+pub mod beeper;
+pub mod font;
+pub mod host;
+pub mod instruction;
+pub mod chip8;
+#[cfg(feature = "std")]
+pub mod palette;
+pub mod platform;
+pub mod rng;
+
+pub use crate::chip8::{Chip8, Chip8Error, StateError};
+pub use crate::font::FONT_SET;
+pub use crate::host::{Keypad, Screen};
+pub use crate::instruction::{Instruction, OpCodeInstruction};
+pub use crate::platform::{Platform, Quirks, RngMode};
+pub use crate::rng::{Rng, SeededRng};
+
+pub const CHIP8_WIDTH: usize = 64;
+pub const CHIP8_HEIGHT: usize = 32;
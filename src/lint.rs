@@ -0,0 +1,392 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use chip_8::chip8::{MEMORY_SIZE, NUM_STACK_FRAMES, PROGRAM_START};
+use chip_8::instruction::Instruction;
+
+use crate::analysis::{flow, Flow, Program};
+
+const FONT_END: u32 = 0x50; // LD F, VX points I somewhere in the built-in font
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error, // stops the emulator if the program gets there
+    Warning, // very likely a bug
+    Note, // worth knowing, such as behaviour that changes with a quirk
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+/// Something found at an address in the program.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Finding {
+    pub addr: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X}  {}: {}", self.addr, self.severity, self.message)
+    }
+}
+
+/// Looks through everything the program can reach from 0x200 for the usual bugs, without running
+/// it: instructions that don't decode, jumps that go astray, calls nesting deeper than the stack,
+/// returns with nothing to return to, `LD [I]`/`LD V, [I]` past the end of memory, and
+/// instructions whose behaviour depends on a quirk. Findings are in address order.
+pub fn lint(rom: &[u8]) -> Vec<Finding> {
+    let program = Program::new(rom);
+    let mut findings = BTreeSet::new();
+
+    let i_values = track_i(&program);
+    let data = data_read(&program, &i_values);
+    check_loads_and_stores(&program, &i_values, &mut findings);
+    let subroutines = Walker { program: &program, code: &i_values, data, findings: &mut findings, odd: Vec::new() }.walk();
+    check_stack_depth(&subroutines, &mut findings);
+    check_quirks(&program, i_values.keys(), &mut findings);
+
+    findings.into_iter().collect()
+}
+
+/// What's known of the I register before an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IValue {
+    Unknown, // after a call, since the subroutine may have changed it
+    Range(u32, u32), // somewhere from the first to the second, inclusive
+}
+
+impl IValue {
+    fn join(self, other: IValue) -> IValue {
+        match (self, other) {
+            (IValue::Range(lo, hi), IValue::Range(other_lo, other_hi)) => IValue::Range(lo.min(other_lo), hi.max(other_hi)),
+            _ => IValue::Unknown,
+        }
+    }
+
+    // Past the end of memory is all the same, which also stops loops adding to I from going on forever
+    fn add(self, amount: u32) -> IValue {
+        match self {
+            IValue::Range(lo, hi) => IValue::Range(lo, (hi + amount).min(MEMORY_SIZE as u32 + 0xFF)),
+            IValue::Unknown => IValue::Unknown,
+        }
+    }
+
+    fn exact(self) -> Option<usize> {
+        match self {
+            IValue::Range(lo, hi) if lo == hi => Some(lo as usize),
+            _ => None,
+        }
+    }
+}
+
+// Works out the values I can have before every instruction reachable from the start, following
+// jumps, skips and calls into subroutines. Jumps through V0 can't be followed.
+fn track_i(program: &Program) -> BTreeMap<usize, IValue> {
+    let mut values: BTreeMap<usize, IValue> = BTreeMap::new();
+    let mut queue = vec![(PROGRAM_START, IValue::Range(0, 0))];
+    while let Some((addr, value)) = queue.pop() {
+        if !program.contains(addr) {
+            continue;
+        }
+        let value = match values.get(&addr) {
+            Some(&old) if old.join(value) == old => continue,
+            Some(&old) => old.join(value),
+            None => value,
+        };
+        values.insert(addr, value);
+
+        let instruction = match program.decode(addr) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let after = match instruction {
+            Instruction::LDI(nnn) => IValue::Range(u32::from(nnn), u32::from(nnn)),
+            Instruction::ADDI(_) => value.add(0xFF),
+            Instruction::LDS(_) => IValue::Range(0, FONT_END - 1),
+            Instruction::SR(x) | Instruction::LR(x) => value.join(value.add(u32::from(x) + 1)), // with or without the quirk
+            _ => value,
+        };
+        match flow(instruction) {
+            Flow::Next => queue.push((addr + 2, after)),
+            Flow::Skip => queue.extend_from_slice(&[(addr + 2, after), (addr + 4, after)]),
+            Flow::Jump(target) => queue.push((target, after)),
+            Flow::Call(target) => queue.extend_from_slice(&[(target, after), (addr + 2, IValue::Unknown)]),
+            Flow::Return | Flow::Indirect(_) => (),
+        }
+    }
+    values
+}
+
+// The bytes read as sprites, digits or registers from a known I, so not meant to run.
+fn data_read(program: &Program, i_values: &BTreeMap<usize, IValue>) -> BTreeSet<usize> {
+    let mut data = BTreeSet::new();
+    for (&addr, value) in i_values {
+        let len = match program.decode(addr) {
+            Some(Instruction::DRW(_, _, n)) => usize::from(n),
+            Some(Instruction::LR(x)) => usize::from(x) + 1,
+            _ => continue,
+        };
+        if let Some(i) = value.exact() {
+            data.extend(i..i + len);
+        }
+    }
+    data
+}
+
+fn check_loads_and_stores(program: &Program, i_values: &BTreeMap<usize, IValue>, findings: &mut BTreeSet<Finding>) {
+    for (&addr, &value) in i_values {
+        let (instruction, x) = match program.decode(addr) {
+            Some(instruction @ (Instruction::SR(x) | Instruction::LR(x))) => (instruction, u32::from(x)),
+            _ => continue,
+        };
+        let last = MEMORY_SIZE as u32 - 1;
+        let (severity, message) = match value {
+            IValue::Range(lo, _) if lo + x > last => (Severity::Error, format!("I is {:#05X}, so V{:X} is past the end of memory", lo, x)),
+            IValue::Range(_, hi) if hi + x > last => (Severity::Warning, format!("I may be as high as {:#05X}, which puts V{:X} past the end of memory", hi, x)),
+            _ => continue,
+        };
+        findings.insert(Finding { addr, severity, message: format!("{}: {}", instruction, message) });
+    }
+}
+
+// The calls each subroutine makes, as the address of the call and the subroutine called. The
+// program itself is the subroutine at 0x200.
+type Subroutines = BTreeMap<usize, Vec<(usize, usize)>>;
+
+// Walks the program and each subroutine it calls, stepping over calls, to check where control goes.
+struct Walker<'a> {
+    program: &'a Program<'a>,
+    code: &'a BTreeMap<usize, IValue>, // every instruction reachable from the start
+    data: BTreeSet<usize>,
+    findings: &'a mut BTreeSet<Finding>,
+    odd: Vec<(usize, String)>, // jumps and calls to odd addresses, which run fine unless they land mid-instruction
+}
+
+impl Walker<'_> {
+    fn walk(mut self) -> Subroutines {
+        let mut subroutines = Subroutines::new();
+        let mut entries = vec![PROGRAM_START];
+        while let Some(entry) = entries.pop() {
+            if let Entry::Vacant(vacant) = subroutines.entry(entry) {
+                let calls = vacant.insert(self.walk_subroutine(entry));
+                entries.extend(calls.iter().map(|&(_, target)| target));
+            }
+        }
+
+        if let Some((addr, text)) = self.odd.first() {
+            let message = format!("{} goes to an odd address{}", text, as_do_more(self.odd.len() - 1, "jump or call", "jumps and calls"));
+            self.findings.insert(Finding { addr: *addr, severity: Severity::Note, message });
+        }
+        subroutines
+    }
+
+    fn walk_subroutine(&mut self, entry: usize) -> Vec<(usize, usize)> {
+        let mut calls = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut queue = vec![entry];
+        while let Some(addr) = queue.pop() {
+            if !visited.insert(addr) {
+                continue;
+            }
+            let instruction = match self.program.decode(addr) {
+                Some(instruction) => instruction,
+                None => {
+                    let opcode = self.program.opcode(addr).unwrap_or_default();
+                    let message = format!("{:04X} isn't an instruction, and can be reached from the start", opcode);
+                    self.findings.insert(Finding { addr, severity: Severity::Error, message });
+                    continue;
+                }
+            };
+
+            let targets = match flow(instruction) {
+                Flow::Next => vec![(addr + 2, "runs on to")],
+                Flow::Skip => vec![(addr + 2, "runs on to"), (addr + 4, "skips to")],
+                Flow::Jump(target) => vec![(target, "jumps to")],
+                Flow::Call(target) => {
+                    if self.check_target(addr, instruction, target, "calls") {
+                        calls.push((addr, target));
+                    }
+                    vec![(addr + 2, "returns to")]
+                },
+                Flow::Return if entry == PROGRAM_START => {
+                    let message = "RET with nothing on the stack, since no CALL leads here".to_string();
+                    self.findings.insert(Finding { addr, severity: Severity::Error, message });
+                    vec![]
+                },
+                Flow::Return | Flow::Indirect(_) => vec![],
+            };
+            for (target, verb) in targets {
+                if self.check_target(addr, instruction, target, verb) {
+                    queue.push(target);
+                }
+            }
+        }
+        calls
+    }
+
+    // Whether control can go on to `target`, noting if it goes somewhere it shouldn't.
+    fn check_target(&mut self, addr: usize, instruction: Instruction, target: usize, verb: &str) -> bool {
+        let transfer = verb == "jumps to" || verb == "calls";
+        let problem = if target + 2 > MEMORY_SIZE {
+            (Severity::Error, "past the end of memory".to_string())
+        } else if target < PROGRAM_START {
+            (Severity::Warning, "in the interpreter's memory".to_string())
+        } else if !self.program.contains(target) {
+            (Severity::Warning, "past the end of the program".to_string())
+        } else if transfer && self.code.contains_key(&(target - 1)) {
+            (Severity::Warning, format!("in the middle of the instruction at {:#05X}", target - 1))
+        } else if transfer && self.data.contains(&target) {
+            (Severity::Warning, "in data that's drawn or loaded".to_string())
+        } else {
+            if transfer && target % 2 == 1 {
+                self.odd.push((addr, instruction.to_string()));
+            }
+            return true;
+        };
+        let (severity, problem) = problem;
+        let message = format!("{} {} {:#05X}, {}", instruction, verb, target, problem);
+        self.findings.insert(Finding { addr, severity, message });
+        // Code that runs into data or another instruction still runs, so carry on and see what it does
+        self.program.contains(target) && target >= PROGRAM_START
+    }
+}
+
+fn check_stack_depth(subroutines: &Subroutines, findings: &mut BTreeSet<Finding>) {
+    let mut depths = HashMap::new();
+    for &(addr, target) in &subroutines[&PROGRAM_START] {
+        let depth = 1 + nesting(target, subroutines, &mut depths, &mut BTreeSet::new(), findings);
+        if depth > NUM_STACK_FRAMES {
+            let message = format!("CALL {:#05X} nests calls {} deep, more than the {} the stack holds", target, depth, NUM_STACK_FRAMES);
+            findings.insert(Finding { addr, severity: Severity::Warning, message });
+        }
+    }
+}
+
+// How many calls deep the subroutine at `entry` can go, not counting recursion.
+fn nesting(entry: usize, subroutines: &Subroutines, depths: &mut HashMap<usize, usize>, active: &mut BTreeSet<usize>,
+        findings: &mut BTreeSet<Finding>) -> usize {
+    if let Some(&depth) = depths.get(&entry) {
+        return depth;
+    }
+    active.insert(entry);
+    let mut deepest = 0;
+    for &(addr, target) in &subroutines[&entry] {
+        if active.contains(&target) {
+            let message = format!("CALL {:#05X} can be reached again from inside the subroutine, so the stack overflows unless that stops within {} calls", target, NUM_STACK_FRAMES);
+            findings.insert(Finding { addr, severity: Severity::Warning, message });
+        } else {
+            deepest = deepest.max(1 + nesting(target, subroutines, depths, active, findings));
+        }
+    }
+    active.remove(&entry);
+    depths.insert(entry, deepest);
+    deepest
+}
+
+// Notes the first reachable instruction that each quirk changes, with how many more there are.
+fn check_quirks<'a>(program: &Program, reachable: impl Iterator<Item = &'a usize>, findings: &mut BTreeSet<Finding>) {
+    let mut uses: BTreeMap<&str, Vec<(usize, Instruction)>> = BTreeMap::new();
+    for &addr in reachable {
+        let instruction = match program.decode(addr) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        let quirk = match instruction {
+            Instruction::SHR(x, y) | Instruction::SHL(x, y) if x != y => "shift_uses_vy",
+            Instruction::SR(_) | Instruction::LR(_) => "load_store_increments_i",
+            Instruction::JPV0(_) => "jump_uses_vx",
+            Instruction::OR(..) | Instruction::AND(..) | Instruction::XOR(..) => "logic_resets_vf",
+            Instruction::DRW(..) => "sprites_wrap",
+            _ => continue,
+        };
+        uses.entry(quirk).or_default().push((addr, instruction));
+    }
+
+    for (quirk, uses) in uses {
+        let (addr, instruction) = uses[0];
+        let message = format!("{} depends on the {} quirk{}", instruction, quirk, as_do_more(uses.len() - 1, "instruction", "instructions"));
+        findings.insert(Finding { addr, severity: Severity::Note, message });
+    }
+}
+
+// How many more of the same there are, for the end of a finding.
+fn as_do_more(count: usize, one: &str, many: &str) -> String {
+    match count {
+        0 => String::new(),
+        1 => format!(", as does 1 more {}", one),
+        _ => format!(", as do {} more {}", count, many),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(rom: &[u8]) -> Vec<String> {
+        lint(rom).iter().filter(|finding| finding.severity != Severity::Note).map(Finding::to_string).collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        // LD I, 0x206; DRW V0, V0, 1; JP 0x204; then the sprite
+        assert_eq!(messages(&[0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0xFF, 0x00]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn finds_bad_control_flow() {
+        // SE V0, 0x00; JP 0x205; RET, with the jump landing on the middle of the RET
+        let findings = messages(&[0x30, 0x00, 0x12, 0x05, 0x00, 0xEE, 0xFF, 0xFF]);
+        assert_eq!(findings, [
+            "202  warning: JP 0x205 jumps to 0x205, in the middle of the instruction at 0x204",
+            "204  error: RET with nothing on the stack, since no CALL leads here",
+            "205  error: EEFF isn't an instruction, and can be reached from the start",
+        ]);
+    }
+
+    #[test]
+    fn finds_deep_and_recursive_calls() {
+        // CALL 0x204 then JP 0x202; the subroutine calls itself
+        let findings = messages(&[0x22, 0x04, 0x12, 0x02, 0x22, 0x04, 0x00, 0xEE]);
+        assert_eq!(findings, ["204  warning: CALL 0x204 can be reached again from inside the subroutine, so the stack overflows unless that stops within 16 calls"]);
+
+        // Seventeen subroutines, each calling the next
+        let mut rom = Vec::new();
+        for n in 0..17 {
+            rom.extend_from_slice(&(0x2000 | (PROGRAM_START as u16 + 4 + n * 4)).to_be_bytes());
+            rom.extend_from_slice(&[0x00, 0xEE]);
+        }
+        rom.extend_from_slice(&[0x00, 0xEE]);
+        rom[2..4].copy_from_slice(&[0x12, 0x02]); // the program itself loops after the first call
+        assert_eq!(messages(&rom), ["200  warning: CALL 0x204 nests calls 17 deep, more than the 16 the stack holds"]);
+    }
+
+    #[test]
+    fn finds_stores_past_memory() {
+        // LD I, 0xFFC; LD [I], V7; then adding to I in a loop before loading
+        let findings = messages(&[0xAF, 0xFC, 0xF7, 0x55, 0xF0, 0x1E, 0x12, 0x04]);
+        assert_eq!(findings, ["202  error: LD [I], V7: I is 0xFFC, so V7 is past the end of memory"]);
+        let findings = messages(&[0xAF, 0x00, 0xF0, 0x1E, 0x3F, 0x00, 0x12, 0x02, 0xF3, 0x65, 0x12, 0x0A]);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].starts_with("208  warning: LD V3, [I]: I may be as high as"));
+    }
+
+    #[test]
+    fn notes_quirks() {
+        // SHR V1, V2; SHR V3, V3; OR V1, V2; JP 0x206
+        let notes: Vec<_> = lint(&[0x81, 0x26, 0x83, 0x36, 0x81, 0x21, 0x12, 0x06]).iter().map(Finding::to_string).collect();
+        assert_eq!(notes, [
+            "200  note: SHR V1, V2 depends on the shift_uses_vy quirk",
+            "204  note: OR V1, V2 depends on the logic_resets_vf quirk",
+        ]);
+    }
+}
@@ -1,23 +1,480 @@
+#[cfg(feature = "sdl")]
 extern crate sdl2;
 
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::process;
 
-mod font;
+use chip_8::chip8::{Chip8, MAX_PROGRAM_SIZE};
+use chip_8::{beeper, palette};
+
+mod analysis;
+#[cfg(feature = "sdl")]
+mod bitmap_font;
+mod capture;
+mod cli;
+mod config;
+mod control_flow;
+#[cfg(feature = "sdl")]
 mod drivers;
-mod instruction;
-mod chip8;
+mod emulation;
+mod export;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+mod frontend;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+mod gif_recorder;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+mod keymap;
+mod lint;
+mod movie;
+mod profiler;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+mod rom_database;
+mod tools;
+
+use beeper::Tone;
+use cli::Command;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+use cli::FrontendKind;
+use config::{Config, Profile, QuirksConfig};
+use export::Export;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+use frontend::{Browser, Exit, Frontend};
+#[cfg(any(feature = "sdl", feature = "tui"))]
+use keymap::Keymap;
+use movie::{Movie, MovieSettings, Player};
+use palette::Palette;
+use profiler::Profiler;
 
-const CHIP8_WIDTH: usize = 64;
-const CHIP8_HEIGHT: usize = 32;
+#[cfg(any(feature = "sdl", feature = "tui"))]
+const DEFAULT_SCALE: u32 = 20; // window pixels per CHIP-8 pixel
 
 fn main() {
-    let file_name = env::args().nth(1).expect("Expected a valid game name as argument!");
-	let mut file = File::open(file_name).expect("There was an issue opening the game file.");
-	let mut game_data = Vec::new();
-	file.read_to_end(&mut game_data).expect("Failure to read file.");
-
-    let mut chip8 = chip8::Chip8::new(game_data);
-    chip8.run();
-}
\ No newline at end of file
+    let command = cli::parse_args(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        eprintln!("Run 'chip_8 --help' for usage.");
+        process::exit(2);
+    });
+
+    match command {
+        Command::Help(help) => {
+            let _ = writeln!(io::stdout(), "{}", help);
+        },
+        Command::Run(options) => run(&options),
+        Command::Disasm(options) => {
+            let rom = or_exit(read_rom(options.rom()));
+            exit_on_error(tools::disasm(&rom, &mut io::stdout().lock()).map_err(Into::into));
+        },
+        Command::Trace(options, instructions) => {
+            let setup = or_exit(Setup::load(&options, options.rom()));
+            let mut chip8 = or_exit(setup.create_chip8(&options, options.seed.unwrap_or_else(rand::random)));
+            let mut out = BufWriter::new(io::stdout().lock());
+            exit_on_error(tools::trace(&mut chip8, setup.speed(), instructions, &mut out));
+        },
+        Command::Info(options) => info(&options),
+        Command::Test(options, expect) => test(&options, expect),
+        Command::Lint(options) => lint(&options),
+        Command::Cfg(options) => cfg(&options),
+    }
+}
+
+/// The ROM and the settings it runs with, from the config file and the command line.
+struct Setup {
+    game_data: Vec<u8>,
+    config_path: Option<PathBuf>,
+    config: Config,
+    profile: Profile, // the command line comes first, then the ROM's section of the config, then the rest of the config
+    player: Option<Player>, // the movie to play back, which decides the speed, platform and quirks
+}
+
+impl Setup {
+    fn load(options: &cli::Options, rom: &str) -> Result<Setup, String> {
+        let game_data = read_rom(rom)?;
+        let (config_path, config) = load_config(options)?;
+        let mut profile = options.profile.or(&config.profile(&game_data));
+        let player = options.play_movie.as_ref().map(|path| {
+            Player::load(path, &game_data).map_err(|e| format!("Couldn't play the movie {}: {}", path.display(), e))
+        }).transpose()?;
+
+        // A movie only plays back the same with the settings it was recorded with
+        if let Some(settings) = player.as_ref().and_then(Player::settings) {
+            if options.profile.speed.is_some() || options.profile.platform.is_some() || options.profile.quirks != QuirksConfig::default() {
+                eprintln!("The movie sets the speed, platform and quirks, so they're ignored on the command line");
+            }
+            profile.speed = Some(settings.speed);
+            profile.platform = Some(settings.platform);
+            profile.quirks = QuirksConfig::difference(settings.quirks, settings.platform.quirks());
+        }
+        Ok(Setup { game_data, config_path, config, profile, player })
+    }
+
+    fn speed(&self) -> u32 {
+        self.profile.speed.unwrap_or(emulation::DEFAULT_SPEED)
+    }
+
+    fn create_chip8(&self, options: &cli::Options, seed: u64) -> Result<Chip8, String> {
+        let mut chip8 = Chip8::with_seed(&self.game_data, seed);
+        chip8.set_platform(self.profile.platform.unwrap_or_default());
+        chip8.set_quirks(self.profile.quirks());
+        if let Some(path) = &options.vip_interpreter {
+            let image = fs::read(path).map_err(|e| format!("Couldn't read the VIP interpreter {}: {}", path.display(), e))?;
+            chip8.load_vip_interpreter(&image);
+        }
+        if chip8.rng_mode() != chip8.platform().rng_mode() {
+            eprintln!("RND needs --vip-interpreter FILE to work like the VIP's, so it uses the seeded generator instead");
+        }
+        Ok(chip8)
+    }
+
+    // A movie is played back with the seed it was recorded with, so RND gives the same numbers.
+    // Otherwise the seed is `default_seed` unless one was given.
+    fn movie(&mut self, options: &cli::Options, default_seed: u64) -> Result<(u64, Movie), String> {
+        let player = self.player.take();
+        let settings = MovieSettings { speed: self.speed(), platform: self.profile.platform.unwrap_or_default(), quirks: self.profile.quirks() };
+        let seed = options.seed.or_else(|| player.as_ref().map(Player::seed)).unwrap_or(default_seed);
+        let movie = match (&options.record_movie, player) {
+            (Some(path), _) => Movie::record(path, &self.game_data, seed, settings).map_err(|e| format!("Couldn't create the movie {}: {}", path.display(), e))?,
+            (None, Some(player)) => Movie::play(player),
+            (None, None) => Movie::default(),
+        };
+        Ok((seed, movie))
+    }
+}
+
+// Plays the ROM given, or those picked from the browser until the user quits.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn run(options: &cli::Options) {
+    let mut frontend = None;
+    let mut browser = None;
+    let mut rom = options.rom.clone();
+
+    loop {
+        let path = match rom.take() {
+            Some(path) => path,
+            None => {
+                let browser = browser.get_or_insert_with(|| open_browser(options));
+                let frontend = frontend.get_or_insert_with(|| {
+                    let (_, config) = or_exit(load_config(options));
+                    let profile = options.profile.or(&config.defaults);
+                    start_frontend(options, profile.scale.unwrap_or(DEFAULT_SCALE), profile.audio.tone())
+                });
+                match browser.choose(frontend.as_mut()) {
+                    Some(path) => path.to_string_lossy().into_owned(),
+                    None => return,
+                }
+            },
+        };
+
+        match play(options, &path, &mut frontend) {
+            Ok(Exit::Quit) => return,
+            Ok(Exit::Browse) => {},
+            Err(e) if browser.is_some() => eprintln!("Emulator stopped: {}", e), // back to the browser
+            Err(e) => {
+                drop(frontend.take()); // restore the terminal before printing anything
+                exit_on_error(Err(e));
+            },
+        }
+    }
+}
+
+// Built without a frontend, games can only be run headless.
+#[cfg(not(any(feature = "sdl", feature = "tui")))]
+fn run(options: &cli::Options) {
+    if options.headless_frames.is_none() {
+        eprintln!("chip_8 was built without a frontend, so it can only run headless, with --headless or --screenshot-at-frame");
+        process::exit(1);
+    }
+    exit_on_error(play_headless(options, options.rom()));
+}
+
+// The browser for the ROM directory given on the command line or in the config, or ROMS.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn open_browser(options: &cli::Options) -> Browser {
+    let (_, config) = or_exit(load_config(options));
+    let dir = options.frontend.rom_dir.clone().or_else(|| config.rom_dir.clone()).unwrap_or_else(|| PathBuf::from("ROMS"));
+    let profile = options.profile.or(&config.defaults);
+    let palette = profile.palette().with_colors(options.colors);
+    let browser = Browser::open(&dir, &config, &options.profile, palette).unwrap_or_else(|e| {
+        eprintln!("Couldn't list the ROMs in {}: {}", dir.display(), e);
+        process::exit(1);
+    });
+    if browser.is_empty() {
+        eprintln!("No ROM given, and there are none in {} to pick from", dir.display());
+        process::exit(1);
+    }
+    browser
+}
+
+// Runs one game, in the frontend if there is one already. It is created on first use, sized for this ROM.
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn play(options: &cli::Options, rom: &str, frontend: &mut Option<Box<dyn Frontend>>) -> Result<Exit, Box<dyn Error>> {
+    if options.headless_frames.is_some() {
+        return play_headless(options, rom).map(|()| Exit::Quit);
+    }
+
+    // The frontend may already be up, so errors go back to `run` to be shown once it's closed
+    let mut setup = Setup::load(options, rom)?;
+    let keymap = Keymap::from_config(
+        options.frontend.keymap.as_deref(),
+        setup.config.defaults.keymap.as_ref(),
+        setup.config.rom(&setup.game_data).and_then(|rom| rom.keymap.as_ref()),
+    )?;
+    let palette = setup.profile.palette().with_colors(options.colors);
+    let speed = setup.speed();
+    let tone = setup.profile.audio.tone();
+
+    let (seed, mut movie) = setup.movie(options, rand::random())?;
+    let mut chip8 = setup.create_chip8(options, seed)?;
+    let mut export = create_export(options, tone)?;
+    let mut profiler = options.profile_report.as_ref().map(|_| Profiler::new());
+
+    let scale = setup.profile.scale.unwrap_or(DEFAULT_SCALE);
+    let frontend = frontend.get_or_insert_with(|| start_frontend(options, scale, tone));
+
+    let mut settings = frontend::Settings {
+        keymap,
+        speed,
+        palettes: palette::cycle_from(palette),
+        phosphor: options.frontend.phosphor,
+        phosphor_decay: options.frontend.phosphor_decay,
+        screenshot_scale: options.screenshot_scale,
+        gif_scale: options.frontend.gif_scale,
+        tone,
+        muted: setup.profile.audio.muted.unwrap_or(false),
+        fast_forward: setup.profile.fast_forward.unwrap_or(frontend::DEFAULT_FAST_FORWARD),
+        fast_forward_sound: setup.profile.audio.fast_forward.unwrap_or_default(),
+        scale,
+        stats: options.frontend.stats,
+        states_dir: config::states_dir(),
+        rom_hash: config::rom_hash(&setup.game_data),
+        config_path: setup.config_path,
+    };
+    let result = frontend::run(&mut chip8, frontend.as_mut(), &mut settings, &mut export, &mut movie, &mut profiler);
+    finish_export(export);
+    finish_movie(movie);
+    finish_profile(profiler, options, rom);
+    Ok(result?)
+}
+
+// Runs one game without a frontend, for as long as the command line asked.
+fn play_headless(options: &cli::Options, rom: &str) -> Result<(), Box<dyn Error>> {
+    let mut setup = Setup::load(options, rom)?;
+    let palette = setup.profile.palette().with_colors(options.colors);
+    let (seed, mut movie) = setup.movie(options, rand::random())?;
+    let mut chip8 = setup.create_chip8(options, seed)?;
+    let mut export = create_export(options, setup.profile.audio.tone())?;
+    let mut profiler = options.profile_report.as_ref().map(|_| Profiler::new());
+
+    let result = run_headless(&mut chip8, setup.speed(), &palette, options, &mut export, &mut movie, &mut profiler);
+    finish_export(export);
+    finish_movie(movie);
+    finish_profile(profiler, options, rom);
+    result
+}
+
+fn info(options: &cli::Options) {
+    let setup = or_exit(Setup::load(options, options.rom()));
+    let platform = setup.profile.platform.unwrap_or_default();
+    let (instructions, other) = tools::count_instructions(&setup.game_data);
+    let config = match (&setup.config_path, setup.config.rom(&setup.game_data)) {
+        (Some(path), Some(_)) => format!("{}, with a section for this ROM", path.display()),
+        (Some(path), None) => path.display().to_string(),
+        (None, _) => "none".to_string(),
+    };
+
+    println!("ROM:          {}", options.rom());
+    println!("Size:         {} bytes", setup.game_data.len());
+    println!("SHA-1:        {}", config::rom_hash(&setup.game_data));
+    println!("Decodes as:   {} instructions, {} other words", instructions, other);
+    println!("Config:       {}", config);
+    println!("Platform:     {}", platform);
+    println!("Speed:        {} instructions per frame", setup.speed());
+    println!("Quirks:       {}", tools::describe_quirks(setup.profile.quirks()));
+}
+
+fn test(options: &cli::Options, expect: Option<String>) {
+    let mut setup = or_exit(Setup::load(options, options.rom()));
+    let (seed, mut movie) = or_exit(setup.movie(options, 0)); // the same every time, so the hash is too
+    let mut chip8 = or_exit(setup.create_chip8(options, seed));
+    let mut profiler = options.profile_report.as_ref().map(|_| Profiler::new());
+
+    let frames = options.headless_frames.unwrap_or(0).max(movie.len());
+    let result = emulation::run_headless(&mut chip8, frames, setup.speed(), &mut movie, &mut profiler, |_, _, _| Ok(()));
+    finish_movie(movie);
+    finish_profile(profiler, options, options.rom());
+    exit_on_error(result);
+
+    let hash = tools::display_hash(chip8.display());
+    println!("{}", hash);
+    if let Some(expected) = expect.filter(|expected| *expected != hash) {
+        eprintln!("The display doesn't match: expected {}", expected);
+        process::exit(1);
+    }
+}
+
+fn lint(options: &cli::Options) {
+    let findings = lint::lint(&or_exit(read_rom(options.rom())));
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let count = |severity| findings.iter().filter(|finding| finding.severity == severity).count();
+    let (errors, warnings) = (count(lint::Severity::Error), count(lint::Severity::Warning));
+    println!("{} errors, {} warnings", errors, warnings);
+    if errors + warnings > 0 {
+        process::exit(1);
+    }
+}
+
+fn cfg(options: &cli::Options) {
+    let rom = or_exit(read_rom(options.rom()));
+    let program = analysis::Program::new(&rom);
+    let graph = control_flow::Graph::new(&program);
+    let name = PathBuf::from(options.rom()).file_name().map_or_else(|| options.rom().to_string(), |name| name.to_string_lossy().into_owned());
+    let result = match &options.output {
+        Some(path) => fs::File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            graph.write_dot(&program, &name, &mut out)?;
+            out.flush()
+        }),
+        None => graph.write_dot(&program, &name, &mut io::stdout().lock()),
+    };
+    exit_on_error(result.map_err(Into::into));
+}
+
+fn load_config(options: &cli::Options) -> Result<(Option<PathBuf>, Config), String> {
+    let path = options.config.clone().or_else(config::default_path);
+    let config = match &path {
+        Some(path) => Config::load(path).map_err(|e| format!("Couldn't read the config file {}", e))?,
+        None => Config::default(),
+    };
+    Ok((path, config))
+}
+
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn start_frontend(options: &cli::Options, scale: u32, tone: Tone) -> Box<dyn Frontend> {
+    create_frontend(options, scale, tone).unwrap_or_else(|e| {
+        eprintln!("Couldn't start the {:?} frontend: {}", options.frontend.kind, e);
+        process::exit(1);
+    })
+}
+
+// Reads the whole ROM, or says why it can't be run.
+fn read_rom(path: &str) -> Result<Vec<u8>, String> {
+    let game_data = fs::read(path).map_err(|e| format!("Couldn't read the ROM {}: {}", path, e))?;
+    if game_data.len() > MAX_PROGRAM_SIZE {
+        return Err(format!("The ROM {} is {} bytes, more than the {} that fit in memory", path, game_data.len(), MAX_PROGRAM_SIZE));
+    }
+    Ok(game_data)
+}
+
+// For the commands with no frontend to close first.
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+fn exit_on_error(result: Result<(), Box<dyn Error>>) {
+    if let Err(e) = result {
+        // Output piped into something like `head` that stopped reading isn't a failure
+        if e.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe) {
+            return;
+        }
+        eprintln!("Emulator stopped: {}", e);
+        process::exit(1);
+    }
+}
+
+// Runs without a frontend as fast as possible, exporting every frame and taking the requested screenshot.
+// A movie being played back runs to its end at least.
+fn run_headless(
+    chip8: &mut Chip8,
+    speed: u32,
+    palette: &Palette,
+    options: &cli::Options,
+    export: &mut Export,
+    movie: &mut Movie,
+    profiler: &mut Option<Profiler>,
+) -> Result<(), Box<dyn Error>> {
+    let save_screenshot = |chip8: &Chip8, frame: u64| -> Result<(), Box<dyn Error>> {
+        match &options.screenshot_at_frame {
+            Some((screenshot_frame, path)) if *screenshot_frame == frame => {
+                let image = capture::render(chip8.display(), palette, options.screenshot_scale);
+                capture::save_png(path, &image).map_err(|e| format!("couldn't save screenshot to {}: {}", path.display(), e).into())
+            },
+            _ => Ok(()),
+        }
+    };
+
+    let frames = options.headless_frames.unwrap_or(0).max(movie.len());
+    save_screenshot(chip8, 0)?;
+    emulation::run_headless(chip8, frames, speed, movie, profiler, |frame, chip8, sound_timer_set| {
+        export.frame(chip8.display(), palette, sound_timer_set)?;
+        save_screenshot(chip8, frame)
+    })
+}
+
+fn create_export(options: &cli::Options, tone: Tone) -> Result<Export, String> {
+    Export::create(options.record_video.as_deref(), options.record_audio.as_deref(), options.video_scale, tone)
+        .map_err(|e| format!("Couldn't create export file: {}", e))
+}
+
+fn finish_export(export: Export) {
+    if export.is_recording() {
+        if let Err(e) = export.finish() {
+            eprintln!("Couldn't finish export: {}", e);
+        }
+    }
+}
+
+fn finish_movie(movie: Movie) {
+    if let Err(e) = movie.finish() {
+        eprintln!("Couldn't finish the movie: {}", e);
+    }
+}
+
+// Writes the report for a run with --profile, even one stopped by an error, as that's often what's being looked into.
+fn finish_profile(profiler: Option<Profiler>, options: &cli::Options, rom: &str) {
+    if let (Some(profiler), Some(path)) = (profiler, &options.profile_report) {
+        let result = fs::File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            profiler.write_report(rom, &mut out)?;
+            out.flush()
+        });
+        if let Err(e) = result {
+            eprintln!("Couldn't write the profile to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(any(feature = "sdl", feature = "tui"))]
+fn create_frontend(options: &cli::Options, scale: u32, tone: Tone) -> Result<Box<dyn Frontend>, String> {
+    match options.frontend.kind {
+        FrontendKind::Sdl => create_sdl_frontend(options, scale, tone),
+        FrontendKind::Tui => create_tui_frontend(options),
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn create_sdl_frontend(options: &cli::Options, scale: u32, tone: Tone) -> Result<Box<dyn Frontend>, String> {
+    Ok(Box::new(frontend::SdlFrontend::new(scale, options.frontend.integer_scaling, tone)?))
+}
+
+#[cfg(all(feature = "tui", not(feature = "sdl")))]
+fn create_sdl_frontend(_: &cli::Options, _: u32, _: Tone) -> Result<Box<dyn Frontend>, String> {
+    Err("chip_8 was built without the sdl frontend".to_string())
+}
+
+#[cfg(feature = "tui")]
+fn create_tui_frontend(options: &cli::Options) -> Result<Box<dyn Frontend>, String> {
+    Ok(Box::new(frontend::TuiFrontend::new(options.frontend.color).map_err(|e| e.to_string())?))
+}
+
+#[cfg(all(feature = "sdl", not(feature = "tui")))]
+fn create_tui_frontend(_: &cli::Options) -> Result<Box<dyn Frontend>, String> {
+    Err("chip_8 was built without the tui frontend".to_string())
+}
@@ -0,0 +1,314 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use sha1::{Digest, Sha1};
+
+use chip_8::chip8::{Chip8, NUM_KEYS};
+use chip_8::{Platform, Quirks};
+
+// File layout, all little endian:
+//   "C8M" 0x02, RND seed (u64), SHA-1 of the ROM (20 bytes),
+//   speed (u32), platform (u8, its index in `Platform::ALL`), quirks (u8, see `quirk_bits`)
+//   then for each frame: keypad state (u16, bit N for key N), state hash after the frame (u64)
+// Version 1 movies are the same without the speed, platform and quirks.
+const MAGIC: &[u8; 4] = b"C8M\x02";
+const MAGIC_V1: &[u8; 4] = b"C8M\x01";
+const HEADER_SIZE: usize = 38;
+const HEADER_SIZE_V1: usize = 32;
+const FRAME_SIZE: usize = 10;
+
+/// What a movie was recorded with besides the keys. Playback has to run with the same or the
+/// state hashes won't match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieSettings {
+    pub speed: u32, // instructions per frame
+    pub platform: Platform,
+    pub quirks: Quirks,
+}
+
+/// Recording or playback of the keypad, one frame at a time, so a run can be repeated exactly.
+/// Does nothing when it is neither.
+#[derive(Default)]
+pub struct Movie {
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+}
+
+impl Movie {
+    pub fn record(path: &Path, rom: &[u8], seed: u64, settings: MovieSettings) -> io::Result<Self> {
+        Ok(Movie { recorder: Some(Recorder::create(path, rom, seed, settings)?), player: None })
+    }
+
+    pub fn play(player: Player) -> Self {
+        Movie { recorder: None, player: Some(player) }
+    }
+
+    #[cfg(any(feature = "sdl", feature = "tui"))]
+    pub fn is_active(&self) -> bool {
+        self.recorder.is_some() || self.player.is_some()
+    }
+
+    /// The number of frames in the movie being played, or 0.
+    pub fn len(&self) -> u64 {
+        self.player.as_ref().map_or(0, Player::len)
+    }
+
+    /// The keys to press this frame: the movie's while one is playing, otherwise `live`.
+    pub fn keys(&self, live: [bool; NUM_KEYS]) -> [bool; NUM_KEYS] {
+        match &self.player {
+            Some(player) => player.keys(),
+            None => live,
+        }
+    }
+
+    /// Records the frame just run, or checks it against the movie being played.
+    /// Returns `true` when playback has reached the end of the movie.
+    pub fn frame(&mut self, chip8: &Chip8, keys: [bool; NUM_KEYS]) -> Result<bool, Box<dyn Error>> {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write_frame(keys, chip8.state_hash())?;
+        }
+        if let Some(player) = &mut self.player {
+            player.check(chip8.state_hash())?;
+            if player.is_finished() {
+                self.player = None;
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.recorder {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    fn create(path: &Path, rom: &[u8], seed: u64, settings: MovieSettings) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer, rom, seed, settings)?;
+        Ok(Recorder { writer })
+    }
+
+    fn write_frame(&mut self, keys: [bool; NUM_KEYS], hash: u64) -> io::Result<()> {
+        write_frame(&mut self.writer, keys, hash)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn write_header<W: Write>(writer: &mut W, rom: &[u8], seed: u64, settings: MovieSettings) -> io::Result<()> {
+    let platform = Platform::ALL.iter().position(|&platform| platform == settings.platform).unwrap_or(0) as u8;
+    writer.write_all(MAGIC)?;
+    writer.write_all(&seed.to_le_bytes())?;
+    writer.write_all(&Sha1::digest(rom))?;
+    writer.write_all(&settings.speed.to_le_bytes())?;
+    writer.write_all(&[platform, quirk_bits(settings.quirks)])
+}
+
+// Bit 0 to 4 for each quirk, in the order `Quirks` declares them
+fn quirk_bits(quirks: Quirks) -> u8 {
+    [quirks.shift_uses_vy, quirks.load_store_increments_i, quirks.jump_uses_vx, quirks.logic_resets_vf, quirks.sprites_wrap]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (bit, &on)| bits | (on as u8) << bit)
+}
+
+fn quirks_from_bits(bits: u8) -> Quirks {
+    let on = |bit: u8| bits & (1 << bit) != 0;
+    Quirks { shift_uses_vy: on(0), load_store_increments_i: on(1), jump_uses_vx: on(2), logic_resets_vf: on(3), sprites_wrap: on(4) }
+}
+
+fn write_frame<W: Write>(writer: &mut W, keys: [bool; NUM_KEYS], hash: u64) -> io::Result<()> {
+    let mask = keys.iter().enumerate().fold(0u16, |mask, (key, &pressed)| mask | (pressed as u16) << key);
+    writer.write_all(&mask.to_le_bytes())?;
+    writer.write_all(&hash.to_le_bytes())
+}
+
+struct MovieFrame {
+    keys: u16,
+    hash: u64,
+}
+
+/// A movie loaded for playback.
+pub struct Player {
+    seed: u64,
+    settings: Option<MovieSettings>, // not in version 1 movies
+    frames: Vec<MovieFrame>,
+    position: usize, // frames played so far
+}
+
+impl Player {
+    /// Reads a movie, checking that it was recorded with `rom`.
+    pub fn load(path: &Path, rom: &[u8]) -> Result<Self, Box<dyn Error>> {
+        Player::read(BufReader::new(File::open(path)?), rom)
+    }
+
+    fn read<R: Read>(mut reader: R, rom: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header[..4]).map_err(|_| "not a CHIP-8 movie")?;
+        let header_size = match &header[..4] {
+            magic if magic == MAGIC => HEADER_SIZE,
+            magic if magic == MAGIC_V1 => HEADER_SIZE_V1,
+            _ => return Err("not a CHIP-8 movie".into()),
+        };
+        reader.read_exact(&mut header[4..header_size]).map_err(|_| "not a CHIP-8 movie")?;
+        if header[12..32] != Sha1::digest(rom)[..] {
+            return Err("the movie was recorded with a different ROM".into());
+        }
+        let mut seed = [0; 8];
+        seed.copy_from_slice(&header[4..12]);
+        let settings = match header_size {
+            HEADER_SIZE => Some(MovieSettings {
+                speed: u32::from_le_bytes([header[32], header[33], header[34], header[35]]),
+                platform: *Platform::ALL.get(usize::from(header[36])).ok_or("the movie was recorded for an unknown platform")?,
+                quirks: quirks_from_bits(header[37]),
+            }),
+            _ => None,
+        };
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        if data.len() % FRAME_SIZE != 0 {
+            return Err("the movie is cut short part way through a frame".into());
+        }
+        let frames = data.chunks(FRAME_SIZE).map(|frame| {
+            let mut hash = [0; 8];
+            hash.copy_from_slice(&frame[2..]);
+            MovieFrame { keys: u16::from_le_bytes([frame[0], frame[1]]), hash: u64::from_le_bytes(hash) }
+        }).collect();
+
+        Ok(Player { seed: u64::from_le_bytes(seed), settings, frames, position: 0 })
+    }
+
+    /// The seed the movie was recorded with, which the emulator must be started with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The speed, platform and quirks the movie was recorded with, unless it's too old to say.
+    pub fn settings(&self) -> Option<MovieSettings> {
+        self.settings
+    }
+
+    fn len(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position >= self.frames.len()
+    }
+
+    fn keys(&self) -> [bool; NUM_KEYS] {
+        let mut keys = [false; NUM_KEYS];
+        if let Some(frame) = self.frames.get(self.position) {
+            for (key, pressed) in keys.iter_mut().enumerate() {
+                *pressed = frame.keys & (1 << key) != 0;
+            }
+        }
+        keys
+    }
+
+    fn check(&mut self, hash: u64) -> Result<(), Desync> {
+        let frame = self.position;
+        self.position += 1;
+        match self.frames.get(frame) {
+            Some(expected) if expected.hash != hash => Err(Desync { frame: frame as u64 + 1, expected: expected.hash, actual: hash }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Playback stopped matching the recording.
+#[derive(Debug)]
+pub struct Desync {
+    frame: u64, // counting from 1
+    expected: u64,
+    actual: u64,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "movie desynced at frame {}: state hash {:016x}, expected {:016x}", self.frame, self.actual, self.expected)
+    }
+}
+
+impl Error for Desync {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: MovieSettings = MovieSettings {
+        speed: 12,
+        platform: Platform::CosmacVip,
+        quirks: Quirks { sprites_wrap: true, ..QUIRKS },
+    };
+    const QUIRKS: Quirks = Quirks { shift_uses_vy: true, load_store_increments_i: false, jump_uses_vx: true, logic_resets_vf: false, sprites_wrap: false };
+
+    #[test]
+    fn replay_recorded_frames() {
+        let rom = [0xC0, 0xFF, 0xF1, 0x0A, 0x12, 0x00]; // RND V0 0xFF, LDK V1, JMP 0x200
+        let mut recording = Chip8::with_seed(rom, 99);
+        let mut file = Vec::new();
+        write_header(&mut file, &rom, 99, SETTINGS).unwrap();
+        let mut hashes = Vec::new();
+        for frame in 0..4 {
+            let mut keys = [false; NUM_KEYS];
+            keys[frame] = true;
+            recording.set_keys(keys);
+            recording.cycle().unwrap();
+            write_frame(&mut file, keys, recording.state_hash()).unwrap();
+            hashes.push(recording.state_hash());
+        }
+
+        let mut player = Player::read(&file[..], &rom).unwrap();
+        assert_eq!(player.len(), 4);
+        assert_eq!(player.settings(), Some(SETTINGS));
+        let mut playback = Chip8::with_seed(rom, player.seed());
+        for &hash in hashes.iter() {
+            playback.set_keys(player.keys());
+            playback.cycle().unwrap();
+            assert_eq!(playback.state_hash(), hash);
+            player.check(playback.state_hash()).unwrap();
+        }
+        assert!(player.is_finished());
+
+        let mut player = Player::read(&file[..], &rom).unwrap();
+        let desync = player.check(0).unwrap_err();
+        assert_eq!(desync.frame, 1);
+        assert!(Player::read(&file[..], &[0x00, 0xE0]).is_err());
+    }
+
+    #[test]
+    fn version_1_movies_still_play() {
+        let rom = [0x12, 0x00]; // JMP 0x200
+        let mut file = MAGIC_V1.to_vec();
+        file.extend_from_slice(&7u64.to_le_bytes());
+        file.extend_from_slice(&Sha1::digest(rom));
+        write_frame(&mut file, [false; NUM_KEYS], 0).unwrap();
+
+        let player = Player::read(&file[..], &rom).unwrap();
+        assert_eq!((player.seed(), player.settings(), player.len()), (7, None, 1));
+    }
+
+    #[test]
+    fn unknown_platforms_are_rejected() {
+        let rom = [0x12, 0x00];
+        let mut file = Vec::new();
+        write_header(&mut file, &rom, 0, SETTINGS).unwrap();
+        file[36] = Platform::ALL.len() as u8;
+        assert!(Player::read(&file[..], &rom).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A colour as red, green and blue components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parses `RRGGBB` or `#RRGGBB`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim_start_matches('#');
+        let value = if hex.len() == 6 { u32::from_str_radix(hex, 16).ok() } else { None };
+        match value {
+            Some(value) => Ok(Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8)),
+            None => Err(format!("'{}' isn't a colour, expected RRGGBB hex", s)),
+        }
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}{:02X}{:02X}", self.0, self.1, self.2)
+    }
+}
+
+/// Colours for each pixel value. Index 0 is the background and 1 the foreground;
+/// 2 and 3 are only used by multi-plane modes where a pixel can be in both planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    pub fn color(&self, pixel: u8) -> Rgb {
+        self.colors[(pixel & 0b11) as usize]
+    }
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        PRESETS.iter().find(|palette| palette.name == name).copied()
+    }
+
+    /// This palette with some of its colours replaced.
+    pub fn with_colors(mut self, colors: [Option<Rgb>; 4]) -> Palette {
+        if colors.iter().any(Option::is_some) {
+            self.name = "custom";
+            for (color, custom) in self.colors.iter_mut().zip(colors.iter()) {
+                *color = custom.unwrap_or(*color);
+            }
+        }
+        self
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PRESETS[0]
+    }
+}
+
+pub const PRESETS: [Palette; 7] = [
+    Palette { name: "classic", colors: [Rgb(0x00, 0x00, 0x00), Rgb(0xFF, 0xFF, 0xFF), Rgb(0xAA, 0xAA, 0xAA), Rgb(0x55, 0x55, 0x55)] },
+    Palette { name: "green", colors: [Rgb(0x0A, 0x14, 0x0A), Rgb(0x33, 0xFF, 0x66), Rgb(0x1E, 0x99, 0x3D), Rgb(0x99, 0xFF, 0xB3)] },
+    Palette { name: "amber", colors: [Rgb(0x1A, 0x0E, 0x00), Rgb(0xFF, 0xB0, 0x00), Rgb(0x99, 0x69, 0x00), Rgb(0xFF, 0xD8, 0x80)] },
+    Palette { name: "lcd", colors: [Rgb(0xC4, 0xCB, 0xB8), Rgb(0x2F, 0x33, 0x2C), Rgb(0x7A, 0x80, 0x72), Rgb(0x52, 0x57, 0x4D)] },
+    Palette { name: "octo", colors: [Rgb(0x99, 0x66, 0x00), Rgb(0xFF, 0xCC, 0x00), Rgb(0xFF, 0x66, 0x00), Rgb(0x66, 0x22, 0x00)] },
+    Palette { name: "high-contrast", colors: [Rgb(0x00, 0x00, 0x00), Rgb(0xFF, 0xFF, 0x00), Rgb(0x00, 0xFF, 0xFF), Rgb(0xFF, 0xFF, 0xFF)] },
+    // Okabe-Ito colours, which stay distinct under the common kinds of colour blindness
+    Palette { name: "colorblind", colors: [Rgb(0x00, 0x00, 0x00), Rgb(0xE6, 0x9F, 0x00), Rgb(0x56, 0xB4, 0xE9), Rgb(0x00, 0x9E, 0x73)] },
+];
+
+/// The palettes the cycle hotkey steps through, starting from the one in use.
+pub fn cycle_from(start: Palette) -> Vec<Palette> {
+    let mut palettes: Vec<Palette> = PRESETS.to_vec();
+    match palettes.iter().position(|&palette| palette == start) {
+        Some(index) => palettes.rotate_left(index),
+        None => palettes.insert(0, start), // custom colours come first, then the presets
+    }
+    palettes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rgb() {
+        assert_eq!("#FFB000".parse::<Rgb>(), Ok(Rgb(0xFF, 0xB0, 0x00)));
+        assert_eq!("0a140a".parse::<Rgb>(), Ok(Rgb(0x0A, 0x14, 0x0A)));
+        assert!("FFF".parse::<Rgb>().is_err());
+        assert!("GGGGGG".parse::<Rgb>().is_err());
+    }
+
+    #[test]
+    fn cycle_starts_at_current_palette() {
+        let palettes = cycle_from(Palette::preset("amber").unwrap());
+        assert_eq!(palettes.len(), PRESETS.len());
+        assert_eq!(palettes[0].name, "amber");
+        assert_eq!(palettes[1].name, "lcd");
+    }
+}
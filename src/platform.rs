@@ -0,0 +1,86 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use core::str::FromStr;
+
+/// Machines that ran CHIP-8. Their interpreters differed in small ways that some programs depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Platform {
+	#[default]
+	Chip8, // this emulator's own behaviour
+	CosmacVip, // the original interpreter on the RCA COSMAC VIP
+}
+
+/// Instructions whose behaviour differed between interpreters. Programs written for one often
+/// misbehave with another's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+	pub shift_uses_vy: bool, // 8XY6/8XYE shift VY into VX, rather than shifting VX in place
+	pub load_store_increments_i: bool, // FX55/FX65 leave I just past the last register
+	pub jump_uses_vx: bool, // BXNN jumps to XNN + VX, rather than BNNN to NNN + V0
+	pub logic_resets_vf: bool, // 8XY1/8XY2/8XY3 clear VF
+	pub sprites_wrap: bool, // sprites going off one edge carry on from the other, rather than being cut off
+}
+
+/// Where `RND` gets its numbers from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RngMode {
+	Seeded, // a uniform PRNG, see `SeededRng`
+	CosmacVip, // the VIP interpreter's method, biases and all
+}
+
+impl Platform {
+	pub const ALL: [Platform; 2] = [Platform::Chip8, Platform::CosmacVip];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Platform::Chip8 => "chip8",
+			Platform::CosmacVip => "vip",
+		}
+	}
+
+	pub fn quirks(self) -> Quirks {
+		match self {
+			Platform::Chip8 => Quirks {
+				shift_uses_vy: false,
+				load_store_increments_i: true,
+				jump_uses_vx: false,
+				logic_resets_vf: false,
+				sprites_wrap: true,
+			},
+			Platform::CosmacVip => Quirks {
+				shift_uses_vy: true,
+				load_store_increments_i: true,
+				jump_uses_vx: false,
+				logic_resets_vf: true,
+				sprites_wrap: false,
+			},
+		}
+	}
+
+	pub fn rng_mode(self) -> RngMode {
+		match self {
+			Platform::Chip8 => RngMode::Seeded,
+			Platform::CosmacVip => RngMode::CosmacVip,
+		}
+	}
+}
+
+impl fmt::Display for Platform {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+#[cfg(feature = "std")]
+impl FromStr for Platform {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Platform::ALL.iter().copied().find(|platform| platform.name() == s).ok_or_else(|| {
+			let names: Vec<_> = Platform::ALL.iter().map(|platform| platform.name()).collect();
+			format!("unknown platform '{}', expected one of {}", s, names.join(", "))
+		})
+	}
+}
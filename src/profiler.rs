@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use chip_8::chip8::{Chip8, Chip8Error, MEMORY_SIZE, NUM_STACK_FRAMES};
+use chip_8::instruction::{Instruction, OpCodeInstruction};
+
+const TOP: usize = 10; // entries in each list of the report
+const BAR_WIDTH: u64 = 40; // characters for the most run instruction in the histogram
+
+/// Counts what a program runs, to find where its time goes. Time is measured in instructions run,
+/// which is what a CHIP-8 program is limited by.
+pub struct Profiler {
+    started: Instant,
+    instructions: u64,
+    frames: u64,
+    addresses: Vec<(u64, u16)>, // times run and the opcode, for each address
+    last_run: Vec<u64>, // the instruction count when each address last ran, or 0 if it never has
+    opcodes: Vec<u64>, // times run, for each of the 65536 opcodes
+    loops: BTreeMap<(usize, usize), (u64, u64)>, // times each branch back from the second address to the first was taken, and the instructions run in those iterations
+    calls: Vec<(usize, u64)>, // subroutines running, with the instruction count when they were called
+    subroutines: BTreeMap<usize, (u64, u64)>, // calls that returned, and the instructions run inside them
+    draws: u32, // this frame
+    draws_per_frame: BTreeMap<u32, u64>, // frames that drew so many times
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            started: Instant::now(),
+            instructions: 0,
+            frames: 0,
+            addresses: vec![(0, 0); MEMORY_SIZE],
+            last_run: vec![0; MEMORY_SIZE],
+            opcodes: vec![0; 0x10000],
+            loops: BTreeMap::new(),
+            calls: Vec::new(),
+            subroutines: BTreeMap::new(),
+            draws: 0,
+            draws_per_frame: BTreeMap::new(),
+        }
+    }
+
+    /// Runs one instruction, counting it.
+    pub fn cycle(&mut self, chip8: &mut Chip8) -> Result<(), Chip8Error> {
+        let pc = chip8.pc();
+        let opcode = chip8.next_opcode();
+        chip8.cycle()?;
+        let opcode = match opcode {
+            Some(opcode) => opcode,
+            None => return Ok(()), // the cycle above failed, so this doesn't happen
+        };
+
+        self.instructions += 1;
+        self.addresses[pc] = (self.addresses[pc].0 + 1, opcode);
+        self.last_run[pc] = self.instructions;
+        self.opcodes[usize::from(opcode)] += 1;
+        match OpCodeInstruction::new(opcode).process_opcode() {
+            Some(Instruction::CALL(addr)) => {
+                if self.calls.len() == NUM_STACK_FRAMES {
+                    self.calls.remove(0); // can only happen once the stack has been thrown away with a jump
+                }
+                self.calls.push((usize::from(addr), self.instructions));
+            },
+            Some(Instruction::RET()) => {
+                if let Some((entry, called_at)) = self.calls.pop() {
+                    let (calls, instructions) = self.subroutines.entry(entry).or_default();
+                    *calls += 1;
+                    *instructions += self.instructions - called_at;
+                }
+            },
+            Some(Instruction::DRW(..)) => self.draws += 1,
+            Some(Instruction::LDK(_)) => (), // waiting for a key, which leaves pc where it is
+            _ if chip8.pc() <= pc && self.last_run[chip8.pc()] > 0 => {
+                // This time round is everything run since the start of the loop last ran
+                let (times, inside) = self.loops.entry((chip8.pc(), pc)).or_default();
+                *times += 1;
+                *inside += self.instructions - self.last_run[chip8.pc()] + 1;
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+        *self.draws_per_frame.entry(std::mem::take(&mut self.draws)).or_default() += 1;
+    }
+
+    /// Writes what was counted: the hottest loops, subroutines and instructions, how often each
+    /// instruction ran, and how much drawing each frame did.
+    pub fn write_report(&self, name: &str, out: &mut dyn Write) -> io::Result<()> {
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        writeln!(out, "Profile of {}: {} instructions in {} frames ({:.1}s emulated, {:.1}s real)", name,
+            self.instructions, self.frames, self.frames as f64 / 60.0, self.started.elapsed().as_secs_f64())?;
+
+        writeln!(out, "\nHottest loops, by the instructions run from the start to the branch back each time round, including calls")?;
+        writeln!(out, "(a loop inside another counts towards both):")?;
+        let mut loops: Vec<_> = self.loops.iter().map(|(&(start, end), &(times, inside))| (inside, start, end, times)).collect();
+        loops.sort_by(|a, b| b.cmp(a));
+        for &(inside, start, end, times) in loops.iter().take(TOP) {
+            writeln!(out, "  {:03X}-{:03X}  {:>10} instruction{}  {:5.1}%  {:>8} time{} round", start, end, inside, plural(inside), percent(inside), times, plural(times))?;
+        }
+
+        writeln!(out, "\nSubroutines, by the instructions run inside them and the subroutines they call:")?;
+        let mut subroutines: Vec<_> = self.subroutines.iter().map(|(&entry, &(calls, inside))| (inside, entry, calls)).collect();
+        subroutines.sort_by(|a, b| b.cmp(a));
+        for &(inside, entry, calls) in subroutines.iter().take(TOP) {
+            writeln!(out, "  {:03X}      {:>10} instruction{}  {:5.1}%  {:>8} call{}, {} instruction{} each", entry, inside, plural(inside), percent(inside),
+                calls, plural(calls), inside / calls, plural(inside / calls))?;
+        }
+
+        writeln!(out, "\nHottest instructions:")?;
+        let mut addresses: Vec<_> = self.addresses.iter().enumerate().filter(|(_, &(count, _))| count > 0).collect();
+        addresses.sort_by(|a, b| b.1.0.cmp(&a.1.0).then(a.0.cmp(&b.0)));
+        for &(addr, &(count, opcode)) in addresses.iter().take(TOP) {
+            let text = OpCodeInstruction::new(opcode).process_opcode().map_or_else(|| "??".to_string(), |instruction| instruction.to_string());
+            writeln!(out, "  {:03X}  {:04X}  {:<16} {:>10}  {:5.1}%", addr, opcode, text, count, percent(count))?;
+        }
+
+        writeln!(out, "\nInstructions run:")?;
+        let mut variants: BTreeMap<&str, u64> = BTreeMap::new();
+        for (opcode, &count) in self.opcodes.iter().enumerate().filter(|(_, &count)| count > 0) {
+            if let Some(instruction) = OpCodeInstruction::new(opcode as u16).process_opcode() {
+                *variants.entry(instruction.name()).or_default() += count;
+            }
+        }
+        let mut variants: Vec<_> = variants.into_iter().collect();
+        variants.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let most = variants.first().map_or(1, |&(_, count)| count);
+        for (variant, count) in &variants {
+            let bar = "#".repeat((count * BAR_WIDTH / most) as usize);
+            writeln!(out, "  {:<5} {:>10}  {:5.1}%  {}", variant, count, percent(*count), bar)?;
+        }
+
+        let draws: u64 = self.draws_per_frame.iter().map(|(&draws, &frames)| u64::from(draws) * frames).sum();
+        let most = self.draws_per_frame.keys().next_back().copied().unwrap_or(0);
+        writeln!(out, "\nDraws per frame: {:.2} on average, {} at most", draws as f64 / self.frames.max(1) as f64, most)?;
+        for (draws, frames) in &self.draws_per_frame {
+            writeln!(out, "  {:>3} draw{}  {:>8} frame{}", draws, plural(u64::from(*draws)), frames, plural(*frames))?;
+        }
+        Ok(())
+    }
+}
+
+fn plural(count: u64) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_loops_calls_and_draws() {
+        // 200: CALL 0x206; JP 0x200
+        // 206: DRW V0, V0, 1; RET
+        let mut chip8 = Chip8::with_seed(vec![0x22, 0x06, 0x12, 0x00, 0x00, 0x00, 0xD0, 0x01, 0x00, 0xEE], 0);
+        let mut profiler = Profiler::new();
+        for _ in 0..2 {
+            for _ in 0..4 {
+                profiler.cycle(&mut chip8).unwrap();
+            }
+            profiler.end_frame();
+        }
+
+        assert_eq!(profiler.instructions, 8);
+        assert_eq!(profiler.addresses[0x206].0, 2);
+        assert_eq!(profiler.subroutines[&0x206], (2, 4)); // DRW and RET, twice
+        assert_eq!(profiler.loops[&(0x200, 0x202)], (2, 8)); // the whole program, calls and all
+        assert_eq!(profiler.draws_per_frame[&1], 2);
+
+        let mut report = Vec::new();
+        profiler.write_report("test.ch8", &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("  200-202           8 instructions  100.0%         2 times round\n"));
+        assert!(report.contains("  206               4 instructions   50.0%         2 calls, 2 instructions each\n"));
+        assert!(report.contains("  CALL           2   25.0%  ########################################\n"));
+        assert!(report.contains("Draws per frame: 1.00 on average, 1 at most\n"));
+        assert!(report.contains("    1 draw         2 frames\n"));
+    }
+
+    #[test]
+    fn loops_count_only_their_own_iterations() {
+        // 200: LD V0, 0
+        // 202: ADD V0, 1; SE V0, 3; JP 0x202
+        // 208: JP 0x208
+        let mut chip8 = Chip8::with_seed(vec![0x60, 0x00, 0x70, 0x01, 0x30, 0x03, 0x12, 0x02, 0x12, 0x08], 0);
+        let mut profiler = Profiler::new();
+        for _ in 0..10 {
+            profiler.cycle(&mut chip8).unwrap();
+        }
+
+        // The last ADD and SE, which fall out of the loop, aren't part of it
+        assert_eq!(profiler.loops[&(0x202, 0x206)], (2, 6));
+        assert_eq!(profiler.loops[&(0x208, 0x208)], (1, 1));
+
+        let mut report = Vec::new();
+        profiler.write_report("test.ch8", &mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("  202-206           6 instructions   60.0%         2 times round\n"));
+        assert!(report.contains("  208-208           1 instruction   10.0%         1 time round\n"));
+    }
+
+    #[test]
+    fn waiting_for_a_key_is_not_a_loop() {
+        let mut chip8 = Chip8::with_seed(vec![0xF0, 0x0A], 0); // LD V0, K
+        let mut profiler = Profiler::new();
+        for _ in 0..3 {
+            profiler.cycle(&mut chip8).unwrap();
+        }
+        assert_eq!(profiler.instructions, 3);
+        assert!(profiler.loops.is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+/// Where `RND` gets its numbers from on platforms that use a generator, rather than the VIP's method.
+/// Hosts can supply their own, such as a microcontroller's hardware generator.
+pub trait Rng {
+	fn next_u8(&mut self) -> u8;
+
+	/// Everything needed to carry on the same sequence, which save states and state hashes include.
+	/// Generators that can't be repeated can leave this as 0.
+	fn state(&self) -> u64 {
+		0
+	}
+
+	/// Carries on from a `state` given earlier.
+	fn restore(&mut self, _state: u64) {}
+}
+
+/// Random numbers for `RND`, from a seed so that runs can be repeated exactly.
+///
+/// This is SplitMix64, which is small, fast and gives good numbers from any seed, including
+/// zero. Its whole state is one `u64`, so it is easy to hash and to save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeededRng {
+	state: u64,
+}
+
+impl SeededRng {
+	pub fn new(seed: u64) -> SeededRng {
+		SeededRng { state: seed }
+	}
+
+	pub fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+
+	pub fn next_u8(&mut self) -> u8 {
+		(self.next_u64() >> 56) as u8 // the high bits are the best mixed
+	}
+
+	pub fn state(&self) -> u64 {
+		self.state
+	}
+}
+
+impl Rng for SeededRng {
+	fn next_u8(&mut self) -> u8 {
+		SeededRng::next_u8(self)
+	}
+
+	fn state(&self) -> u64 {
+		self.state
+	}
+
+	fn restore(&mut self, state: u64) {
+		self.state = state;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn same_seed_same_numbers() {
+		let mut a = SeededRng::new(42);
+		let mut b = SeededRng::new(42);
+		let mut c = SeededRng::new(43);
+		let a: Vec<u8> = (0..16).map(|_| a.next_u8()).collect();
+		let b: Vec<u8> = (0..16).map(|_| b.next_u8()).collect();
+		let c: Vec<u8> = (0..16).map(|_| c.next_u8()).collect();
+		assert_eq!(a, b);
+		assert_ne!(a, c);
+	}
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const BUNDLED: &str = include_str!("rom_database.txt");
+
+/// Titles of known ROMs by the SHA-1 of their contents, from the list bundled with the emulator
+/// and any `titles.txt` next to the ROMs.
+pub struct RomDatabase {
+    titles: HashMap<String, String>,
+}
+
+impl RomDatabase {
+    pub fn bundled() -> Self {
+        let mut database = RomDatabase { titles: HashMap::new() };
+        database.add(BUNDLED);
+        database
+    }
+
+    /// Adds the titles in a file, replacing any already known for the same ROMs. A missing file is fine.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.add(&text);
+                Ok(())
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn title(&self, hash: &str) -> Option<&str> {
+        self.titles.get(hash).map(String::as_str)
+    }
+
+    // Lines are a hash and a title separated by whitespace. Blank lines and lines starting with `#` are skipped.
+    fn add(&mut self, text: &str) {
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let mut parts = line.splitn(2, char::is_whitespace);
+            if let (Some(hash), Some(title)) = (parts.next(), parts.next()) {
+                self.titles.insert(hash.to_ascii_lowercase(), title.trim().to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn later_titles_win() {
+        let mut database = RomDatabase::bundled();
+        database.add("# comment\n\n607C4F7F4E4DCE9F99D96B3182BFE7E88BB090EE  Pong (1 player)\nnot-a-line\n");
+        assert_eq!(database.title("607c4f7f4e4dce9f99d96b3182bfe7e88bb090ee"), Some("Pong (1 player)"));
+        assert_eq!(database.title("5c28a5f85289c9d859f95fd5eadbdcb1c30bb08b"), Some("Space Invaders (David Winter)"));
+    }
+}
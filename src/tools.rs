@@ -0,0 +1,88 @@
+use std::error::Error;
+use std::io::{self, Write};
+
+use sha1::{Digest, Sha1};
+
+use chip_8::chip8::{Chip8, PROGRAM_START};
+use chip_8::instruction::OpCodeInstruction;
+use chip_8::platform::Quirks;
+use chip_8::{CHIP8_WIDTH, CHIP8_HEIGHT};
+
+
+/// Lists the program one instruction per two bytes, as they would be decoded if run from there.
+/// Data mixed in with the code decodes as nonsense or not at all, since nothing marks it apart.
+pub fn disasm(rom: &[u8], out: &mut dyn Write) -> io::Result<()> {
+    for (i, word) in rom.chunks(2).enumerate() {
+        let addr = PROGRAM_START + i * 2;
+        match *word {
+            [high, low] => {
+                let opcode = u16::from_be_bytes([high, low]);
+                match OpCodeInstruction::new(opcode).process_opcode() {
+                    Some(instruction) => writeln!(out, "{:03X}  {:04X}  {}", addr, opcode, instruction)?,
+                    None => writeln!(out, "{:03X}  {:04X}  ??", addr, opcode)?,
+                }
+            },
+            [byte] => writeln!(out, "{:03X}  {:02X}    ??", addr, byte)?,
+            _ => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+/// Runs `instructions` instructions, `speed` to a frame, writing each before it runs along with I and the registers.
+pub fn trace(chip8: &mut Chip8, speed: u32, instructions: u64, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let mut traced = 0;
+    while traced < instructions {
+        for _ in 0..speed {
+            if traced == instructions {
+                break;
+            }
+            let decoded = chip8.next_opcode().map(|opcode| (opcode, OpCodeInstruction::new(opcode).process_opcode()));
+            let text = match &decoded {
+                Some((opcode, Some(instruction))) => format!("{:04X}  {}", opcode, instruction),
+                Some((opcode, None)) => format!("{:04X}  ??", opcode),
+                None => "----".to_string(),
+            };
+            let registers: Vec<String> = chip8.registers().iter().map(|value| format!("{:02X}", value)).collect();
+            writeln!(out, "{:03X}  {:<22} I={:03X}  V={}", chip8.pc(), text, chip8.i(), registers.join(" "))?;
+
+            chip8.cycle()?;
+            traced += 1;
+        }
+        chip8.tick_timers();
+    }
+    Ok(())
+}
+
+/// Counts the words in the program that decode as instructions, and those that don't.
+pub fn count_instructions(rom: &[u8]) -> (usize, usize) {
+    let decodes = |word: &[u8]| match *word {
+        [high, low] => OpCodeInstruction::new(u16::from_be_bytes([high, low])).process_opcode().is_some(),
+        _ => false,
+    };
+    let instructions = rom.chunks(2).filter(|word| decodes(word)).count();
+    (instructions, rom.chunks(2).len() - instructions)
+}
+
+/// The quirks that are on, by the names the config file uses, or "none".
+pub fn describe_quirks(quirks: Quirks) -> String {
+    let quirks = [
+        ("shift_uses_vy", quirks.shift_uses_vy),
+        ("load_store_increments_i", quirks.load_store_increments_i),
+        ("jump_uses_vx", quirks.jump_uses_vx),
+        ("logic_resets_vf", quirks.logic_resets_vf),
+        ("sprites_wrap", quirks.sprites_wrap),
+    ];
+    let on: Vec<&str> = quirks.iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+    if on.is_empty() { "none".to_string() } else { on.join(", ") }
+}
+
+/// SHA-1 of the display, one byte per pixel, in lower case hex. Two runs that draw the same
+/// picture give the same hash.
+pub fn display_hash(display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> String {
+    let mut hasher = Sha1::new();
+    for row in display.iter() {
+        hasher.update(row);
+    }
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,52 @@
+//! The interpreter as a microcontroller host uses it, built without `std` (`cargo test
+//! --no-default-features`): its own generator for `RND`, keypad and screen, and save states in a
+//! fixed-size buffer. The thumbv7em job in CI checks it also builds for a target without `std`.
+
+#![cfg(not(feature = "std"))]
+
+use chip_8::chip8::{NUM_KEYS, STATE_LEN};
+use chip_8::{Chip8, Keypad, Rng, Screen, CHIP8_HEIGHT, CHIP8_WIDTH};
+
+// Always the same number, like a hardware generator stuck on one value
+struct FixedRng(u8);
+
+impl Rng for FixedRng {
+    fn next_u8(&mut self) -> u8 {
+        self.0
+    }
+}
+
+struct NoKeys;
+
+impl Keypad for NoKeys {
+    fn keys(&mut self) -> [bool; NUM_KEYS] {
+        [false; NUM_KEYS]
+    }
+}
+
+struct Panel([[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]);
+
+impl Screen for Panel {
+    fn draw(&mut self, display: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+        self.0 = *display;
+    }
+}
+
+#[test]
+fn runs_with_the_hosts_own_rng_keypad_and_screen() {
+    // RND V0, 0xFF; LD F, V0; DRW V1, V1, 5; JP 0x206
+    let program = [0xC0, 0xFF, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+    let mut chip8 = Chip8::with_rng(&program[..], FixedRng(0x0A));
+    let mut panel = Panel([[0; CHIP8_WIDTH]; CHIP8_HEIGHT]);
+    chip8.run_frame(10, &mut NoKeys, &mut panel).unwrap();
+
+    assert_eq!(chip8.registers()[0], 0x0A);
+    assert_eq!(panel.0[0][..5], [1, 1, 1, 1, 0]); // the top of the font's A
+    assert_eq!(panel.0[1][..5], [1, 0, 0, 1, 0]);
+
+    let mut state = [0; STATE_LEN];
+    chip8.save_state_to(&mut state);
+    let mut restored = Chip8::with_rng(&program[..], FixedRng(0));
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.state_hash(), chip8.state_hash());
+}